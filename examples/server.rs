@@ -0,0 +1,17 @@
+use bRAC::proto::{server::Server, Error};
+use std::{sync::Arc, thread};
+
+fn main() -> Result<(), Error> {
+    let server = Arc::new(Server::in_memory());
+
+    thread::spawn({
+        let server = server.clone();
+        move || {
+            if let Err(e) = server.listen("wrac://127.0.0.1") {
+                println!("wrac listener error: {e}");
+            }
+        }
+    });
+
+    server.listen("rac://127.0.0.1") // both listeners share the same history
+}
@@ -1,9 +1,16 @@
+use chrono::FixedOffset;
 use clap::Parser;
 use serde_default::DefaultFromSerde;
 use serde_yml;
-use std::{error::Error, fs, path::PathBuf};
+use std::{collections::BTreeMap, error::Error, fs, path::PathBuf, time::Duration};
 
-use super::SERVER_LIST;
+use crate::proto::{proxy::proxy_from_env, ConnectOptions, Dialect, RacUrl, TlsMode};
+
+use super::{
+    connection::Backoff,
+    message::{parse_timezone, DATE_FORMAT},
+    UserAgent, SERVER_LIST,
+};
 
 const MESSAGE_FORMAT: &str = "\u{B9AC}\u{3E70}<{name}> {text}";
 
@@ -31,6 +38,37 @@ pub fn default_host() -> String {
 pub fn default_message_format() -> String {
     MESSAGE_FORMAT.to_string()
 }
+pub fn default_date_format() -> String {
+    DATE_FORMAT.to_string()
+}
+
+pub fn default_timeout() -> usize {
+    15000
+}
+pub fn default_max_frame_size() -> usize {
+    16777216 // 16MB
+}
+pub fn default_max_message_size() -> usize {
+    536870912 // 512MB
+}
+pub fn default_reconnect_delay() -> usize {
+    1000
+}
+pub fn default_max_reconnect_delay() -> usize {
+    60000
+}
+pub fn default_ping_interval() -> usize {
+    30000
+}
+pub fn default_history_page_size() -> usize {
+    8192
+}
+pub fn default_reconnect_attempts() -> usize {
+    5
+}
+pub fn default_tls_mode() -> TlsMode {
+    TlsMode::Tofu
+}
 
 pub fn default_servers() -> Vec<String> {
     SERVER_LIST.to_vec()
@@ -62,24 +100,164 @@ pub struct Config {
     pub show_other_ip: bool,
     #[serde(default = "default_true")]
     pub chunked_enabled: bool,
+    /// Probe server capabilities on connect and use them instead of `chunked_enabled` and the dialect
+    #[serde(default = "default_true")]
+    pub probe_enabled: bool,
+    /// Check auth support on probing with a message from a random non-existent user,
+    /// if the history has no unauthenticated messages (server without auth may show it in the chat)
+    #[serde(default)]
+    pub probe_auth: bool,
     #[serde(default = "default_true")]
     pub formatting_enabled: bool,
     #[serde(default = "default_true")]
     pub commands_enabled: bool,
+    /// Proxy URL or comma separated proxy chain for the hosts that are not in `proxies`
     #[serde(default)]
     pub proxy: Option<String>,
+    /// Proxy chains by host (the first proxy is connected first), empty chain is a direct connection
+    #[serde(default)]
+    pub proxies: BTreeMap<String, Vec<String>>,
+    #[serde(default = "default_tls_mode")]
+    pub tls_mode: TlsMode,
+    #[serde(default = "default_timeout")]
+    pub connect_timeout: usize,
+    #[serde(default = "default_timeout")]
+    pub read_timeout: usize,
+    #[serde(default = "default_timeout")]
+    pub write_timeout: usize,
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    #[serde(default)]
+    pub wrac_headers: BTreeMap<String, String>,
+    /// Interval of WebSocket pings on idle WRAC connection in milliseconds, 0 is disabled
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: usize,
+    /// Server dialects by host, servers that are not here are auto-detected
+    #[serde(default)]
+    pub dialects: BTreeMap<String, Dialect>,
+    /// Auth support by host, servers that are not here use the probed one
+    /// (sender names are verified only on servers with auth)
+    #[serde(default)]
+    pub auth_support: BTreeMap<String, bool>,
+    /// Server timezones by host (like `+03:00` or `UTC`), servers that are not here are in the local timezone
+    #[serde(default)]
+    pub timezones: BTreeMap<String, String>,
+    /// Clients added to the built-in user agents (they are checked first and replace built-ins with the same name)
+    #[serde(default)]
+    pub user_agents: Vec<UserAgent>,
+    /// Names of the clients whose messages are not shown
+    #[serde(default)]
+    pub hidden_clients: Vec<String>,
+    /// Format of the shown message dates in the local timezone (chrono strftime)
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// Size of the older history page loaded on scroll-up in bytes
+    #[serde(default = "default_history_page_size")]
+    pub history_page_size: usize,
+    #[serde(default = "default_reconnect_delay")]
+    pub reconnect_delay: usize,
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay: usize,
+    #[serde(default = "default_reconnect_attempts")]
+    pub reconnect_attempts: usize,
     #[serde(default = "default_true")]
     pub notifications_enabled: bool,
     #[serde(default = "default_true")]
     pub new_ui_enabled: bool,
     #[serde(default)]
     pub debug_logs: bool,
+    /// Record all connections to the capture file (read `proto::record`)
+    #[serde(default)]
+    pub record_enabled: bool,
+    /// Capture file, None is `capture.log` near the config file
+    #[serde(default)]
+    pub record_path: Option<String>,
     #[serde(default)]
     pub avatar: Option<String>,
     #[serde(default = "default_servers")]
     pub servers: Vec<String>,
 }
 
+impl Config {
+    /// Timeouts are in milliseconds, zero sizes are unlimited, recorder is not opened here
+    pub fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            connect_timeout: Duration::from_millis(self.connect_timeout as u64),
+            read_timeout: Duration::from_millis(self.read_timeout as u64),
+            write_timeout: Duration::from_millis(self.write_timeout as u64),
+            max_frame_size: Some(self.max_frame_size).filter(|o| *o != 0),
+            max_message_size: Some(self.max_message_size).filter(|o| *o != 0),
+            headers: self
+                .wrac_headers
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            recorder: None,
+        }
+    }
+
+    /// Proxy chain selected for the current host in `proxies` or `proxy`
+    ///
+    /// returns None if nothing is selected (then the environment is used)
+    pub fn proxy_route(&self) -> Option<Vec<String>> {
+        match self.proxies.get(&self.host) {
+            Some(chain) => Some(chain.clone()),
+            None => self.proxy.as_ref().map(|o| {
+                o.split(',')
+                    .map(|o| o.trim().to_string())
+                    .filter(|o| !o.is_empty())
+                    .collect()
+            }),
+        }
+    }
+
+    /// Proxy chain for the current host: selected in config or from `ALL_PROXY`/`HTTPS_PROXY` (Unix sockets have none)
+    pub fn server_proxy(&self) -> Option<String> {
+        match self.proxy_route() {
+            Some(chain) => Some(chain.join(",")).filter(|o| !o.is_empty()),
+            None => {
+                let url = RacUrl::parse(&self.host).ok().filter(|o| !o.is_unix())?;
+                proxy_from_env(&url.host.name(), url.is_ssl())
+            }
+        }
+    }
+
+    /// Capture file for recording
+    pub fn capture_path(&self) -> PathBuf {
+        self.record_path
+            .clone()
+            .map(PathBuf::from)
+            .unwrap_or_else(get_capture_path)
+    }
+
+    /// Dialect selected for the current host
+    pub fn dialect(&self) -> Option<Dialect> {
+        self.dialects.get(&self.host).copied()
+    }
+
+    /// Auth support selected for the current host
+    pub fn server_auth(&self) -> Option<bool> {
+        self.auth_support.get(&self.host).copied()
+    }
+
+    /// Timezone selected for the current host
+    ///
+    /// returns None if nothing is selected or it is invalid (then the local timezone is used)
+    pub fn server_timezone(&self) -> Option<FixedOffset> {
+        parse_timezone(self.timezones.get(&self.host)?)
+    }
+
+    /// Reconnect delays are in milliseconds
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            base: Duration::from_millis(self.reconnect_delay as u64),
+            max: Duration::from_millis(self.max_reconnect_delay as u64),
+        }
+    }
+}
+
 #[cfg(target_os = "windows")]
 pub fn get_config_path() -> PathBuf {
     use std::env;
@@ -102,6 +280,16 @@ pub fn get_config_path() -> PathBuf {
         .join("config.yml")
 }
 
+/// Path of the pinned certificates file (TOFU), near the config file
+pub fn get_known_hosts_path() -> PathBuf {
+    get_config_path().with_file_name("known_hosts")
+}
+
+/// Path of the default capture file of the recorded connections, near the config file
+pub fn get_capture_path() -> PathBuf {
+    get_config_path().with_file_name("capture.log")
+}
+
 pub fn load_config(path: PathBuf) -> Result<Config, Box<dyn Error>> {
     if !fs::exists(&path).unwrap_or_default() {
         if fs::exists("bRAC/config.yml").unwrap_or_default() {
@@ -178,6 +366,10 @@ pub struct Args {
     #[arg(long)]
     pub chunked_enabled: Option<bool>,
     #[arg(long)]
+    pub probe_enabled: Option<bool>,
+    #[arg(long)]
+    pub probe_auth: Option<bool>,
+    #[arg(long)]
     pub formatting_enabled: Option<bool>,
     #[arg(long)]
     pub commands_enabled: Option<bool>,
@@ -188,9 +380,55 @@ pub struct Args {
     #[arg(long)]
     pub proxy: Option<String>,
     #[arg(long)]
+    pub tls_mode: Option<TlsMode>,
+    #[arg(long)]
+    pub connect_timeout: Option<usize>,
+    #[arg(long)]
+    pub read_timeout: Option<usize>,
+    #[arg(long)]
+    pub write_timeout: Option<usize>,
+    #[arg(long)]
+    pub max_frame_size: Option<usize>,
+    #[arg(long)]
+    pub max_message_size: Option<usize>,
+    /// WebSocket handshake header, example: "Origin: https://example.com"
+    #[arg(long = "wrac-header")]
+    pub wrac_headers: Vec<String>,
+    #[arg(long)]
+    pub ping_interval: Option<usize>,
+    /// Dialect of the server (auto-detected if not set)
+    #[arg(long)]
+    pub dialect: Option<Dialect>,
+    /// Timezone of the server, example: "+03:00" (local if not set)
+    #[arg(long)]
+    pub server_timezone: Option<String>,
+    /// Does the server support auth (probed if not set)
+    #[arg(long)]
+    pub server_auth: Option<bool>,
+    /// Hide messages of the client, example: "clRAC"
+    #[arg(long = "hide-client")]
+    pub hidden_clients: Vec<String>,
+    /// Format of the message dates, example: "%H:%M"
+    #[arg(long)]
+    pub date_format: Option<String>,
+    #[arg(long)]
+    pub history_page_size: Option<usize>,
+    #[arg(long)]
+    pub reconnect_delay: Option<usize>,
+    #[arg(long)]
+    pub max_reconnect_delay: Option<usize>,
+    #[arg(long)]
+    pub reconnect_attempts: Option<usize>,
+    #[arg(long)]
     pub avatar: Option<String>,
     #[arg(long)]
     pub debug_logs: bool,
+    /// Record all connections to the capture file
+    #[arg(long)]
+    pub record_enabled: Option<bool>,
+    /// Capture file (default is capture.log near the config file)
+    #[arg(long)]
+    pub record_path: Option<String>,
     // TODO: add servers
 }
 
@@ -205,6 +443,63 @@ impl Args {
         if let Some(v) = self.proxy.clone() {
             config.proxy = Some(v)
         }
+        if let Some(v) = self.tls_mode {
+            config.tls_mode = v
+        }
+        if let Some(v) = self.connect_timeout {
+            config.connect_timeout = v
+        }
+        if let Some(v) = self.read_timeout {
+            config.read_timeout = v
+        }
+        if let Some(v) = self.write_timeout {
+            config.write_timeout = v
+        }
+        if let Some(v) = self.max_frame_size {
+            config.max_frame_size = v
+        }
+        if let Some(v) = self.max_message_size {
+            config.max_message_size = v
+        }
+        if let Some(v) = self.ping_interval {
+            config.ping_interval = v
+        }
+        if let Some(v) = self.dialect {
+            config.dialects.insert(config.host.clone(), v);
+        }
+        if let Some(v) = self.server_timezone.clone() {
+            config.timezones.insert(config.host.clone(), v);
+        }
+        if let Some(v) = self.server_auth {
+            config.auth_support.insert(config.host.clone(), v);
+        }
+        for client in &self.hidden_clients {
+            if !config.hidden_clients.contains(client) {
+                config.hidden_clients.push(client.clone());
+            }
+        }
+        if let Some(v) = self.date_format.clone() {
+            config.date_format = v
+        }
+        if let Some(v) = self.history_page_size {
+            config.history_page_size = v
+        }
+        if let Some(v) = self.reconnect_delay {
+            config.reconnect_delay = v
+        }
+        if let Some(v) = self.max_reconnect_delay {
+            config.max_reconnect_delay = v
+        }
+        if let Some(v) = self.reconnect_attempts {
+            config.reconnect_attempts = v
+        }
+        for header in &self.wrac_headers {
+            if let Some((name, value)) = header.split_once(":") {
+                config
+                    .wrac_headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
         if let Some(v) = self.message_format.clone() {
             config.message_format = v
         }
@@ -235,6 +530,12 @@ impl Args {
         if let Some(v) = self.chunked_enabled {
             config.chunked_enabled = v
         }
+        if let Some(v) = self.probe_enabled {
+            config.probe_enabled = v
+        }
+        if let Some(v) = self.probe_auth {
+            config.probe_auth = v
+        }
         if let Some(v) = self.formatting_enabled {
             config.formatting_enabled = v
         }
@@ -253,5 +554,11 @@ impl Args {
         if self.debug_logs {
             config.debug_logs = true
         }
+        if let Some(v) = self.record_enabled {
+            config.record_enabled = v
+        }
+        if let Some(v) = self.record_path.clone() {
+            config.record_path = Some(v)
+        }
     }
 }
@@ -0,0 +1,46 @@
+use std::{fmt, time::Duration};
+
+use rand::random;
+
+/// State of the server connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Last request to the server was successful
+    Connected,
+    /// Requests are failing, attempt is the number of failed requests in a row
+    Reconnecting(usize),
+    /// Server is not responding after all reconnect attempts, or nothing was requested yet
+    Offline,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connected => write!(f, "Connected"),
+            ConnectionState::Reconnecting(attempt) => write!(f, "Reconnecting (attempt {attempt})"),
+            ConnectionState::Offline => write!(f, "Offline"),
+        }
+    }
+}
+
+/// Exponential backoff with jitter
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Delay after the first failure
+    pub base: Duration,
+    /// Delay is never bigger than this
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Delay before the next attempt
+    ///
+    /// attempt - number of failed attempts in a row (starting from 1)
+    ///
+    /// returns random delay between the half and the full `base * 2^(attempt - 1)`
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31) as u32;
+        let delay = self.base.saturating_mul(1 << exp).min(self.max);
+        delay / 2 + delay.mul_f64(random::<f64>() / 2.0)
+    }
+}
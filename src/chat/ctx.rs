@@ -1,12 +1,28 @@
-use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
-    mpsc::Sender,
-    Arc, RwLock,
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
 use rand::random;
 
-use super::config::Config;
+use crate::proto::{
+    connect, probe, read_history_page, send_ping, tls::KnownHosts, ConnectOptions, Dialect, Error,
+    RacStream, RacUrl, Recorder, ServerCapabilities, TlsVerification,
+};
+
+use super::{
+    config::{get_known_hosts_path, Config},
+    connection::ConnectionState,
+    parse_message, ChatMessage, UserAgents,
+};
+
+/// Failed probes of the host before it is used without probing
+pub const PROBE_ATTEMPTS: usize = 3;
 
 pub struct Context {
     pub registered: RwLock<Option<String>>,
@@ -16,6 +32,28 @@ pub struct Context {
     pub packet_size: AtomicUsize,
     pub name: RwLock<String>,
     pub is_focused: AtomicBool,
+    /// Kept WRAC connection with the generation it was created in
+    pub connection: Mutex<Option<(usize, RacStream)>>,
+    /// Bumped on config change, so the kept connection is dropped by the next operation
+    /// (without waiting for the connection lock in the GUI thread)
+    pub connection_generation: AtomicUsize,
+    /// Last time the kept connection was used or pinged
+    pub last_used: Mutex<Instant>,
+    pub known_hosts: Arc<KnownHosts>,
+    pub connection_state: RwLock<ConnectionState>,
+    pub failures: AtomicUsize,
+    pub state_listeners: RwLock<Vec<Sender<ConnectionState>>>,
+    pub detected_dialect: RwLock<Option<Dialect>>,
+    pub capabilities: RwLock<HashMap<String, ServerCapabilities>>,
+    /// Failed probes in a row by host, the host is not probed after [`PROBE_ATTEMPTS`]
+    pub probe_failures: RwLock<HashMap<String, usize>>,
+    pub history_start: AtomicUsize,
+    /// Opened capture file, if recording is enabled
+    pub recorder: Mutex<Option<Recorder>>,
+    /// Built-in and config clients
+    pub user_agents: RwLock<Arc<UserAgents>>,
+    /// Names of the senders of authenticated messages on the current server
+    pub verified_names: RwLock<HashSet<String>>,
 }
 
 impl Context {
@@ -33,6 +71,20 @@ impl Context {
                     .unwrap_or_else(|| format!("Anon#{:X}", random::<u16>())),
             ),
             is_focused: AtomicBool::new(true),
+            connection: Mutex::new(None),
+            connection_generation: AtomicUsize::default(),
+            last_used: Mutex::new(Instant::now()),
+            known_hosts: Arc::new(KnownHosts::load(get_known_hosts_path())),
+            connection_state: RwLock::new(ConnectionState::Offline),
+            failures: AtomicUsize::default(),
+            state_listeners: RwLock::new(Vec::new()),
+            detected_dialect: RwLock::new(None),
+            capabilities: RwLock::new(HashMap::new()),
+            probe_failures: RwLock::new(HashMap::new()),
+            history_start: AtomicUsize::default(),
+            recorder: Mutex::new(None),
+            user_agents: RwLock::new(Arc::new(UserAgents::with_custom(&config.user_agents))),
+            verified_names: RwLock::new(HashSet::new()),
         }
     }
 
@@ -49,6 +101,316 @@ impl Context {
         *self.registered.write().unwrap() = None;
         *self.messages.write().unwrap() = Vec::new();
         self.packet_size.store(0, Ordering::SeqCst);
+        self.history_start.store(0, Ordering::SeqCst);
+        self.connection_generation.fetch_add(1, Ordering::SeqCst);
+        self.failures.store(0, Ordering::SeqCst);
+        self.set_connection_state(ConnectionState::Offline);
+        *self.detected_dialect.write().unwrap() = None;
+        self.probe_failures.write().unwrap().clear();
+        *self.recorder.lock().unwrap() = None;
+        *self.user_agents.write().unwrap() = Arc::new(UserAgents::with_custom(&config.user_agents));
+        self.verified_names.write().unwrap().clear();
+    }
+
+    pub fn user_agents(&self) -> Arc<UserAgents> {
+        self.user_agents.read().unwrap().clone()
+    }
+
+    /// Remember the verified sender of the message
+    ///
+    /// returns true if the message is sent without auth with the name of a registered user
+    /// (seen in authenticated messages, or ours after /login)
+    pub fn check_sender(&self, message: &ChatMessage) -> bool {
+        if let Some(author) = message.verified_author() {
+            self.verified_names
+                .write()
+                .unwrap()
+                .insert(author.to_string());
+        }
+
+        message.is_impersonation(&self.verified_names.read().unwrap())
+            || (self.registered.read().unwrap().is_some()
+                && message.is_impersonation(&HashSet::from([self.name()])))
+    }
+
+    /// Parse message line with the dialect, timezone, clients and auth support of the current server
+    pub fn parse_message(&self, message: String) -> Option<ChatMessage> {
+        parse_message(
+            message,
+            self.dialect(),
+            self.config(|o| o.server_timezone()),
+            &self.user_agents(),
+            self.server_auth(),
+        )
+    }
+
+    /// Connection options with the recorder, capture file is opened on the first use
+    pub fn connect_options(&self) -> ConnectOptions {
+        let mut options = self.config(|o| o.connect_options());
+
+        if self.config(|o| o.record_enabled) {
+            let mut recorder = self.recorder.lock().unwrap();
+            if recorder.is_none() {
+                *recorder = Recorder::open(self.config(|o| o.capture_path())).ok();
+            }
+            options.recorder = recorder.clone();
+        }
+
+        options
+    }
+
+    pub fn tls_verification(&self) -> TlsVerification {
+        TlsVerification::new(self.config(|o| o.tls_mode), self.known_hosts.clone())
+    }
+
+    /// Dialect of the server: selected in config, probed, auto-detected or RACv2.0
+    pub fn dialect(&self) -> Dialect {
+        self.config(|o| o.dialect())
+            .or(self.capabilities().and_then(|o| o.dialect))
+            .or(*self.detected_dialect.read().unwrap())
+            .unwrap_or_default()
+    }
+
+    /// Is auth known to be supported by the server: selected in config or probed
+    ///
+    /// Names are not verified without it, so it is false if it is unknown
+    pub fn server_auth(&self) -> bool {
+        self.config(|o| o.server_auth())
+            .or(self.capabilities().and_then(|o| o.auth))
+            .unwrap_or_default()
+    }
+
+    /// Probed capabilities of the current host (None if probing is disabled or not done yet)
+    pub fn capabilities(&self) -> Option<ServerCapabilities> {
+        if !self.config(|o| o.probe_enabled) {
+            return None;
+        }
+
+        self.capabilities
+            .read()
+            .unwrap()
+            .get(&self.config(|o| o.host.clone()))
+            .copied()
+    }
+
+    /// Probe capabilities of the current host and cache them
+    pub fn probe(&self) -> Result<ServerCapabilities, Error> {
+        let host = self.config(|o| o.host.clone());
+
+        let capabilities = probe(
+            &host,
+            self.config(|o| o.server_proxy()),
+            &self.tls_verification(),
+            &self.connect_options(),
+            self.config(|o| o.probe_auth),
+        )?;
+
+        self.capabilities
+            .write()
+            .unwrap()
+            .insert(host, capabilities);
+
+        Ok(capabilities)
+    }
+
+    /// Probe the current host if it is not probed yet
+    ///
+    /// Failures are counted, so a server that is down or refuses the probe
+    /// is not probed on every request, after [`PROBE_ATTEMPTS`] the config is used
+    pub fn probe_if_needed(&self) {
+        if !self.config(|o| o.probe_enabled) || self.capabilities().is_some() {
+            return;
+        }
+
+        let host = self.config(|o| o.host.clone());
+
+        if self
+            .probe_failures
+            .read()
+            .unwrap()
+            .get(&host)
+            .copied()
+            .unwrap_or_default()
+            >= PROBE_ATTEMPTS
+        {
+            return;
+        }
+
+        if self.probe().is_err() {
+            *self
+                .probe_failures
+                .write()
+                .unwrap()
+                .entry(host)
+                .or_default() += 1;
+        }
+    }
+
+    /// URL to connect to: the host from config, with the framing found by the probe
+    /// (WRAC server may be set as `rac://` and vice versa)
+    pub fn server_url(&self) -> String {
+        let host = self.config(|o| o.host.clone());
+
+        match (self.capabilities(), RacUrl::parse(&host)) {
+            (Some(capabilities), Ok(mut url)) if capabilities.wrac != url.is_wrac() => {
+                url.scheme = url.scheme.with_wrac(capabilities.wrac);
+                url.to_string()
+            }
+            _ => host,
+        }
+    }
+
+    /// Is chunked reading used: probed or selected in config
+    pub fn chunked_enabled(&self) -> bool {
+        self.capabilities()
+            .and_then(|o| o.chunked)
+            .unwrap_or_else(|| self.config(|o| o.chunked_enabled))
+    }
+
+    /// Detect dialect by the message lines, if it is not selected or detected yet
+    pub fn detect_dialect(&self, messages: &[String]) {
+        if self.config(|o| o.dialect()).is_some() {
+            return;
+        }
+
+        let mut detected = self.detected_dialect.write().unwrap();
+
+        if detected.is_none() {
+            *detected = Dialect::detect(messages);
+        }
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.connection_state.read().unwrap()
+    }
+
+    /// Get connection state changes
+    ///
+    /// returns receiver that gets every new state until it is dropped
+    pub fn subscribe_state(&self) -> Receiver<ConnectionState> {
+        let (sender, receiver) = channel();
+        self.state_listeners.write().unwrap().push(sender);
+        receiver
+    }
+
+    fn set_connection_state(&self, state: ConnectionState) {
+        let mut current = self.connection_state.write().unwrap();
+
+        if *current == state {
+            return;
+        }
+
+        *current = state;
+
+        self.state_listeners
+            .write()
+            .unwrap()
+            .retain(|o| o.send(state).is_ok());
+    }
+
+    /// Delay before the next request to the server, None if connected
+    pub fn reconnect_delay(&self) -> Option<Duration> {
+        match self.failures.load(Ordering::SeqCst) {
+            0 => None,
+            failures => Some(self.config(|o| o.backoff()).delay(failures)),
+        }
+    }
+
+    /// Run operation on the server connection
+    ///
+    /// WRAC connection is kept alive between operations and reconnected once if the operation fails,
+    /// for RAC a new connection is created every time, because server closes it after each request
+    ///
+    /// Connection state is updated by the result: every failure in a row is a reconnect attempt,
+    /// and after `reconnect_attempts` of them the server is offline
+    pub fn with_connection<T>(
+        &self,
+        operation: impl FnMut(&mut RacStream) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let result = self.run_on_connection(operation);
+
+        if matches!(result, Ok(_) | Err(Error::HistoryReset { .. })) {
+            self.failures.store(0, Ordering::SeqCst);
+            self.set_connection_state(ConnectionState::Connected);
+        } else {
+            let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+            self.set_connection_state(if failures > self.config(|o| o.reconnect_attempts) {
+                ConnectionState::Offline
+            } else {
+                ConnectionState::Reconnecting(failures)
+            });
+        }
+
+        result
+    }
+
+    fn run_on_connection<T>(
+        &self,
+        mut operation: impl FnMut(&mut RacStream) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut connection = self.lock_connection();
+        let generation = self.connection_generation.load(Ordering::SeqCst);
+        *self.last_used.lock().unwrap() = Instant::now();
+
+        if let Some((_, stream)) = connection.as_mut() {
+            match operation(stream) {
+                Ok(result) => return Ok(result),
+                // connection is fine, the history on the server is not
+                Err(e @ Error::HistoryReset { .. }) => return Err(e),
+                Err(_) => *connection = None,
+            }
+        }
+
+        let mut stream = connect(
+            &self.server_url(),
+            self.config(|o| o.server_proxy()),
+            &self.tls_verification(),
+            &self.connect_options(),
+        )?;
+
+        let result = operation(&mut stream)?;
+
+        if let RacStream::WRAC(_) = stream {
+            *connection = Some((generation, stream));
+        }
+
+        Ok(result)
+    }
+
+    /// Lock the kept connection, it is dropped if the config was changed since it was created
+    fn lock_connection(&self) -> MutexGuard<'_, Option<(usize, RacStream)>> {
+        let mut connection = self.connection.lock().unwrap();
+        let generation = self.connection_generation.load(Ordering::SeqCst);
+
+        if connection.as_ref().is_some_and(|o| o.0 != generation) {
+            *connection = None;
+        }
+
+        connection
+    }
+
+    /// Ping the kept WRAC connection if it was not used for `ping_interval`
+    ///
+    /// Connection is dropped if the ping fails, so the next operation reconnects
+    pub fn keepalive(&self) {
+        let interval = self.config(|o| o.ping_interval);
+        if interval == 0 {
+            return;
+        }
+
+        let mut connection = self.lock_connection();
+        let mut last_used = self.last_used.lock().unwrap();
+
+        if last_used.elapsed() < Duration::from_millis(interval as u64) {
+            return;
+        }
+        *last_used = Instant::now();
+
+        if let Some((_, stream)) = connection.as_mut() {
+            if send_ping(stream).is_err() {
+                *connection = None;
+            }
+        }
     }
 
     pub fn config<T>(&self, map: fn(&Config) -> T) -> T {
@@ -63,6 +425,9 @@ impl Context {
         self.messages.read().unwrap().clone()
     }
 
+    /// Replace messages with the whole history read without chunks
+    ///
+    /// Older pages are not loaded in this mode, the next full read would clear them
     pub fn put_messages_packet(
         &self,
         max_length: usize,
@@ -70,6 +435,7 @@ impl Context {
         packet_size: usize,
     ) {
         self.packet_size.store(packet_size, Ordering::SeqCst);
+        self.history_start.store(0, Ordering::SeqCst);
         let mut messages = messages;
         if messages.len() > max_length {
             messages.drain(max_length..);
@@ -77,16 +443,66 @@ impl Context {
         *self.messages.write().unwrap() = messages;
     }
 
+    /// Add messages read after the last packet
+    ///
+    /// If the history was reset (packet size is zero), messages are the whole history
+    /// and the old ones are cleared
+    ///
+    /// start - offset of the first message in the server history, if it is the whole history
     pub fn add_messages_packet(
         &self,
         max_length: usize,
         messages: Vec<String>,
         packet_size: usize,
+        start: usize,
     ) {
-        self.packet_size.store(packet_size, Ordering::SeqCst);
+        if self.packet_size.swap(packet_size, Ordering::SeqCst) == 0 {
+            self.messages.write().unwrap().clear();
+            self.history_start.store(start, Ordering::SeqCst);
+        }
         self.add_message(max_length, messages);
     }
 
+    /// Offset of the first loaded message in the server history, zero if the whole history is loaded
+    /// or older pages are not loaded (without chunked reading)
+    pub fn history_start(&self) -> usize {
+        self.history_start.load(Ordering::SeqCst)
+    }
+
+    /// Load the page of the history before the loaded messages
+    ///
+    /// Page is made bigger until it has the start of a message
+    ///
+    /// returns older messages, that are added to the start (empty if the whole history is loaded)
+    pub fn load_older_messages(&self) -> Result<Vec<String>, Error> {
+        let end = self.history_start();
+        let mut page_size = self.config(|o| o.history_page_size).max(1);
+
+        if end == 0 {
+            return Ok(Vec::new());
+        }
+
+        let (messages, start) = loop {
+            match self.with_connection(|o| read_history_page(o, end, page_size, self.dialect()))? {
+                (_, start) if start == end => page_size = page_size.saturating_mul(2),
+                page => break page,
+            }
+        };
+
+        self.history_start.store(start, Ordering::SeqCst);
+        self.messages
+            .write()
+            .unwrap()
+            .splice(0..0, messages.clone());
+
+        Ok(messages)
+    }
+
+    /// Forget the read history, so the next read is full (after [`Error::HistoryReset`])
+    pub fn reset_history(&self) {
+        self.packet_size.store(0, Ordering::SeqCst);
+    }
+
     pub fn add_message(&self, max_length: usize, messages: Vec<String>) {
         self.messages.write().unwrap().append(&mut messages.clone());
         if self.messages.read().unwrap().len() > max_length {
@@ -94,13 +510,3 @@ impl Context {
         }
     }
 }
-
-#[macro_export]
-macro_rules! connect_rac {
-    ($ctx:ident) => {
-        &mut connect(
-            &$ctx.config(|o| o.host.clone()),
-            $ctx.config(|o| o.proxy.clone()),
-        )?
-    };
-}
@@ -4,7 +4,7 @@ use std::error::Error;
 use std::hash::{DefaultHasher, Hasher};
 use std::io::Read;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicBool;
 use std::sync::Mutex;
 use std::sync::{atomic::Ordering, mpsc::channel, Arc, RwLock};
 use std::thread;
@@ -20,20 +20,28 @@ use adw::prelude::*;
 use adw::{Application, ApplicationWindow};
 use libadwaita::gdk::Texture;
 use libadwaita::gtk::gdk_pixbuf::InterpType;
-use libadwaita::gtk::{Button, Entry, Label, Picture};
-use libadwaita::{self as adw, Avatar, Breakpoint, BreakpointCondition, Dialog, OverlaySplitView};
+use libadwaita::gtk::{Button, Entry, Image, Label, Picture};
+use libadwaita::{
+    self as adw, AlertDialog, Avatar, Breakpoint, BreakpointCondition, Dialog, OverlaySplitView,
+    ResponseAppearance,
+};
 
 use adw::gtk;
 use gtk::gdk_pixbuf::{Pixbuf, PixbufLoader};
-use gtk::{Box as GtkBox, CssProvider, Orientation, ScrolledWindow, Settings};
+use gtk::{
+    Box as GtkBox, CssProvider, Orientation, PositionType, ScrolledWindow, Settings, Widget,
+};
 
-use crate::chat::grab_avatar;
+use crate::proto::proxy::{self, proxy_from_env};
+use crate::proto::tls::PinStore;
+use crate::proto::ProxyUrl;
 
 use super::config::get_config_path;
 use super::{
     config::{save_config, Config},
+    connection::ConnectionState,
     ctx::Context,
-    print_message, recv_tick, sanitize_message,
+    print_message, recv_tick, sanitize_message, ChatMessage,
 };
 
 mod page;
@@ -63,7 +71,10 @@ struct UiModel {
     #[cfg(all(not(feature = "libnotify"), not(feature = "notify-rust")))]
     notifications: Arc<RwLock<Vec<String>>>,
     avatars: Arc<Mutex<HashMap<u64, Vec<Avatar>>>>,
-    latest_sign: Arc<AtomicU64>,
+    latest_message: Arc<Mutex<Option<ChatMessage>>>,
+    pin_warning: Arc<Mutex<Option<String>>>,
+    connection_status: Image,
+    history_loading: Arc<AtomicBool>,
 }
 
 thread_local!(
@@ -91,6 +102,61 @@ pub fn add_chat_messages(ctx: Arc<Context>, messages: Vec<String>) {
         .send((messages, false));
 }
 
+/// Warn that the server certificate doesn't match the pinned one (shown once per host)
+pub fn show_pin_mismatch(ctx: Arc<Context>, host: String, expected: String, actual: String) {
+    timeout_add_once(Duration::ZERO, move || {
+        GLOBAL.with(|global| {
+            let Some(ui) = &*global.borrow() else {
+                return;
+            };
+
+            let mut pin_warning = ui.pin_warning.lock().unwrap();
+
+            if pin_warning.as_ref() == Some(&host) {
+                return;
+            }
+
+            *pin_warning = Some(host.clone());
+
+            let dialog = AlertDialog::builder()
+                .heading("Server certificate has changed")
+                .body(format!(
+                    "The certificate of {host} doesn't match the pinned one. \
+                    Someone may be intercepting your connection.\n\n\
+                    Pinned: {expected}\n\nReceived: {actual}"
+                ))
+                .build();
+
+            dialog.add_responses(&[
+                ("cancel", "Stay disconnected"),
+                ("trust", "Trust new certificate"),
+            ]);
+            dialog.set_response_appearance("trust", ResponseAppearance::Destructive);
+            dialog.set_default_response(Some("cancel"));
+            dialog.set_close_response("cancel");
+
+            dialog.connect_response(None, move |_, response| {
+                if response == "trust" {
+                    if let Err(e) = ctx.known_hosts.pin(&host, &actual) {
+                        if ctx.config(|o| o.debug_logs) {
+                            let _ =
+                                print_message(ctx.clone(), format!("Pin certificate error: {e}"));
+                        }
+                    }
+
+                    GLOBAL.with(|global| {
+                        if let Some(ui) = &*global.borrow() {
+                            *ui.pin_warning.lock().unwrap() = None;
+                        }
+                    });
+                }
+            });
+
+            dialog.present(Some(&ui.window));
+        });
+    });
+}
+
 fn load_pixbuf(data: &[u8]) -> Result<Pixbuf, Box<dyn Error>> {
     let loader = PixbufLoader::new();
     loader.write(data)?;
@@ -387,6 +453,10 @@ fn build_ui(ctx: Arc<Context>, app: &Application) -> UiModel {
 
     header.pack_start(&toggle_button);
 
+    let connection_status = Image::new();
+    update_connection_status(&connection_status, ctx.connection_state());
+    header.pack_end(&connection_status);
+
     let window = ApplicationWindow::builder()
         .application(app)
         .title(&format!(
@@ -425,10 +495,26 @@ fn build_ui(ctx: Arc<Context>, app: &Application) -> UiModel {
         #[cfg(all(not(feature = "libnotify"), not(feature = "notify-rust")))]
         notifications: Arc::new(RwLock::new(Vec::<String>::new())),
         avatars: Arc::new(Mutex::new(HashMap::new())),
-        latest_sign: Arc::new(AtomicU64::new(0)),
+        latest_message: Arc::new(Mutex::new(None)),
+        pin_warning: Arc::new(Mutex::new(None)),
+        connection_status,
+        history_loading: Arc::new(AtomicBool::new(false)),
     }
 }
 
+/// Show connection state in the header bar indicator
+fn update_connection_status(image: &Image, state: ConnectionState) {
+    let (icon, class) = match state {
+        ConnectionState::Connected => ("network-transmit-receive-symbolic", "success"),
+        ConnectionState::Reconnecting(_) => ("network-error-symbolic", "warning"),
+        ConnectionState::Offline => ("network-offline-symbolic", "error"),
+    };
+
+    image.set_icon_name(Some(icon));
+    image.set_css_classes(&[class]);
+    image.set_tooltip_text(Some(&state.to_string()));
+}
+
 fn setup(_: &Application, ctx: Arc<Context>, ui: UiModel) {
     let (sender, receiver) = channel();
 
@@ -436,6 +522,32 @@ fn setup(_: &Application, ctx: Arc<Context>, ui: UiModel) {
 
     run_recv_loop(ctx.clone());
 
+    thread::spawn({
+        let receiver = ctx.subscribe_state();
+        move || {
+            while let Ok(state) = receiver.recv() {
+                timeout_add_once(Duration::ZERO, move || {
+                    GLOBAL.with(|global| {
+                        if let Some(ui) = &*global.borrow() {
+                            update_connection_status(&ui.connection_status, state);
+                        }
+                    });
+                });
+            }
+        }
+    });
+
+    ui.chat_scrolled.connect_edge_reached({
+        let ctx = ctx.clone();
+        let loading = ui.history_loading.clone();
+
+        move |_, position| {
+            if position == PositionType::Top {
+                load_older_history(ctx.clone(), loading.clone());
+            }
+        }
+    });
+
     ui.window.connect_notify(Some("is-active"), {
         let ctx = ctx.clone();
 
@@ -499,54 +611,7 @@ fn setup(_: &Application, ctx: Arc<Context>, ui: UiModel) {
                             }
                         });
 
-                        if ctx.config(|o| !o.new_ui_enabled) {
-                            return;
-                        }
-
-                        thread::spawn(move || {
-                            for message in messages.iter() {
-                                let Some(avatar_url) = grab_avatar(message) else {
-                                    continue;
-                                };
-                                let avatar_id = get_avatar_id(&avatar_url);
-
-                                let Some(avatar) = load_avatar(
-                                    &avatar_url,
-                                    ctx.config(|o| o.proxy.clone()),
-                                    ctx.config(|o| o.max_avatar_size as usize),
-                                ) else {
-                                    println!("cant load avatar: {avatar_url} request error");
-                                    continue;
-                                };
-                                let Ok(pixbuf) = load_pixbuf(&avatar) else {
-                                    println!("cant load avatar: {avatar_url} pixbuf error");
-                                    continue;
-                                };
-                                let Some(pixbuf) =
-                                    pixbuf.scale_simple(32, 32, InterpType::Bilinear)
-                                else {
-                                    println!("cant load avatar: {avatar_url} scale image error");
-                                    continue;
-                                };
-                                let texture = Texture::for_pixbuf(&pixbuf);
-
-                                timeout_add_once(Duration::ZERO, {
-                                    move || {
-                                        GLOBAL.with(|global| {
-                                            if let Some(ui) = &*global.borrow() {
-                                                if let Some(pics) =
-                                                    ui.avatars.lock().unwrap().remove(&avatar_id)
-                                                {
-                                                    for pic in pics {
-                                                        pic.set_custom_image(Some(&texture));
-                                                    }
-                                                }
-                                            }
-                                        });
-                                    }
-                                });
-                            }
-                        });
+                        load_avatars(ctx, messages);
                     }
                 });
             }
@@ -554,6 +619,89 @@ fn setup(_: &Application, ctx: Arc<Context>, ui: UiModel) {
     });
 }
 
+/// Load avatars of the shown messages in a new thread
+fn load_avatars(ctx: Arc<Context>, messages: Arc<Vec<String>>) {
+    if ctx.config(|o| !o.new_ui_enabled) {
+        return;
+    }
+
+    thread::spawn(move || {
+        for message in messages.iter() {
+            let Some(avatar_url) = ctx.parse_message(message.clone()).and_then(|o| o.avatar) else {
+                continue;
+            };
+            let avatar_id = get_avatar_id(&avatar_url);
+
+            let Some(avatar) = load_avatar(
+                &avatar_url,
+                ctx.config(|o| o.proxy_route()),
+                ctx.config(|o| o.connect_options().connect_timeout),
+                ctx.config(|o| o.max_avatar_size as usize),
+            ) else {
+                println!("cant load avatar: {avatar_url} request error");
+                continue;
+            };
+            let Ok(pixbuf) = load_pixbuf(&avatar) else {
+                println!("cant load avatar: {avatar_url} pixbuf error");
+                continue;
+            };
+            let Some(pixbuf) = pixbuf.scale_simple(32, 32, InterpType::Bilinear) else {
+                println!("cant load avatar: {avatar_url} scale image error");
+                continue;
+            };
+            let texture = Texture::for_pixbuf(&pixbuf);
+
+            timeout_add_once(Duration::ZERO, {
+                move || {
+                    GLOBAL.with(|global| {
+                        if let Some(ui) = &*global.borrow() {
+                            if let Some(pics) = ui.avatars.lock().unwrap().remove(&avatar_id) {
+                                for pic in pics {
+                                    pic.set_custom_image(Some(&texture));
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+        }
+    });
+}
+
+/// Load the older history page and show it above the messages
+///
+/// loading - is the page loading now, so scrolling doesn't start it twice
+fn load_older_history(ctx: Arc<Context>, loading: Arc<AtomicBool>) {
+    if ctx.history_start() == 0 || loading.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(move || {
+        let messages = match ctx.load_older_messages() {
+            Ok(messages) => messages,
+            Err(e) => {
+                if ctx.config(|o| o.debug_logs) {
+                    let _ = print_message(ctx.clone(), format!("Load history error: {e}"));
+                }
+                Vec::new()
+            }
+        };
+        let messages = Arc::new(messages);
+
+        timeout_add_once(Duration::ZERO, move || {
+            GLOBAL.with(|global| {
+                if let Some(ui) = &*global.borrow() {
+                    on_prepend_messages(ctx.clone(), ui, &messages);
+                }
+            });
+
+            loading.store(false, Ordering::SeqCst);
+
+            load_avatars(ctx, messages);
+        });
+    });
+}
+
 fn load_css(is_dark_theme: bool) {
     let provider = CssProvider::new();
     provider.load_from_data(&format!(
@@ -633,26 +781,54 @@ fn send_notification(_: Arc<Context>, ui: &UiModel, title: &str, message: &str)
     ui.notifications.write().unwrap().push(id);
 }
 
+/// Send notification about the chat message (titled with the author name)
+fn notify_message(ctx: Arc<Context>, ui: &UiModel, message: &ChatMessage) {
+    let title = match &message.author {
+        Some(name) => format!("{name}'s Message"),
+        None => "System Message".to_string(),
+    };
+
+    send_notification(ctx, ui, &title, &glib::markup_escape_text(&message.text));
+}
+
 fn get_avatar_id(url: &str) -> u64 {
     let mut hasher = DefaultHasher::new();
     hasher.write(url.as_bytes());
     hasher.finish()
 }
 
-fn load_avatar(url: &str, proxy: Option<String>, response_limit: usize) -> Option<Vec<u8>> {
-    let client = if let Some(proxy) = proxy {
-        let proxy = if proxy.starts_with("socks5://") {
-            proxy
-        } else {
-            format!("socks5://{proxy}")
-        };
-
-        reqwest::blocking::Client::builder()
-            .proxy(reqwest::Proxy::all(&proxy).ok()?)
+/// Load avatar by the same route as the server connection
+///
+/// route - proxy chain of the server, None to use the environment
+/// timeout - connection timeout for the proxy chain
+fn load_avatar(
+    url: &str,
+    route: Option<Vec<String>>,
+    timeout: Duration,
+    response_limit: usize,
+) -> Option<Vec<u8>> {
+    let route = route.unwrap_or_else(|| {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| proxy_from_env(url.host_str()?, url.scheme() == "https"))
+            .into_iter()
+            .collect()
+    });
+    let chain: Vec<ProxyUrl> = route
+        .iter()
+        .map(|o| ProxyUrl::parse(o))
+        .collect::<Result<_, _>>()
+        .ok()?;
+
+    let client = match chain.as_slice() {
+        [] => reqwest::blocking::Client::new(),
+        // socks5h is the default, http and https are sent to reqwest with basic auth as is
+        [proxy] => reqwest::blocking::Client::builder()
+            .proxy(reqwest::Proxy::all(proxy.to_string()).ok()?)
             .build()
-            .ok()?
-    } else {
-        reqwest::blocking::Client::new()
+            .ok()?,
+        // reqwest can't use proxy chains
+        chain => return proxy::http_get(chain, url, timeout, response_limit).ok(),
     };
 
     client.get(url).send().ok().and_then(|mut resp| {
@@ -677,50 +853,77 @@ fn load_avatar(url: &str, proxy: Option<String>, response_limit: usize) -> Optio
     })
 }
 
-// creates sign that expires in 0-20 minutes
-fn get_message_sign(name: &str, date: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    hasher.write(name.as_bytes());
-    hasher.write(date[..date.len() - 2].as_bytes());
-    hasher.finish()
-}
-
-/// returns message sign
-fn on_add_message(ctx: Arc<Context>, ui: &UiModel, message: String, notify: bool) {
+/// Message widget, None if there is nothing to show
+fn build_message_box(
+    ctx: Arc<Context>,
+    ui: &UiModel,
+    message: String,
+    notify: bool,
+) -> Option<Widget> {
     let notify = notify && ctx.config(|c| c.notifications_enabled);
 
     let formatting_enabled = ctx.config(|c| c.formatting_enabled);
 
-    let Some(sanitized) = (if formatting_enabled {
-        sanitize_message(message.clone())
+    let sanitized = if formatting_enabled {
+        sanitize_message(message.clone())?
     } else {
-        Some(message.clone())
-    }) else {
-        return;
+        message.clone()
     };
 
     if sanitized.is_empty() {
-        return;
+        return None;
     }
 
-    if ctx.config(|o| o.new_ui_enabled) {
-        ui.chat_box.append(&get_new_message_box(
-            ctx.clone(),
-            ui,
-            message,
-            notify,
-            formatting_enabled,
-        ));
+    let parsed = formatting_enabled
+        .then(|| ctx.parse_message(message.clone()))
+        .flatten();
+
+    if let Some(client) = parsed.as_ref().and_then(|o| o.client.as_ref()) {
+        if ctx.config(|o| o.hidden_clients.clone()).contains(client) {
+            return None;
+        }
+    }
+
+    let impersonation = parsed.as_ref().is_some_and(|o| ctx.check_sender(o));
+
+    Some(if ctx.config(|o| o.new_ui_enabled) {
+        get_new_message_box(ctx.clone(), ui, message, parsed, impersonation, notify).upcast()
     } else {
-        ui.chat_box.append(&get_message_box(
-            ctx.clone(),
-            ui,
-            message,
-            notify,
-            formatting_enabled,
-        ));
+        get_message_box(ctx.clone(), ui, message, parsed, impersonation, notify).upcast()
+    })
+}
+
+/// Show older messages above the others, keeping the scroll position
+fn on_prepend_messages(ctx: Arc<Context>, ui: &UiModel, messages: &[String]) {
+    let adjustment = ui.chat_scrolled.vadjustment();
+    let from_bottom = adjustment.upper() - adjustment.value();
+
+    // older messages are not squashed with the newer ones
+    let latest_message = ui.latest_message.lock().unwrap().take();
+
+    let mut previous: Option<Widget> = None;
+
+    for message in messages {
+        if let Some(widget) = build_message_box(ctx.clone(), ui, message.to_string(), false) {
+            ui.chat_box.insert_child_after(&widget, previous.as_ref());
+            previous = Some(widget);
+        }
+    }
+
+    *ui.latest_message.lock().unwrap() = latest_message;
+
+    timeout_add_local_once(Duration::ZERO, move || {
+        adjustment.set_value(adjustment.upper() - from_bottom);
+    });
+}
+
+fn on_add_message(ctx: Arc<Context>, ui: &UiModel, message: String, notify: bool) {
+    let Some(widget) = build_message_box(ctx, ui, message, notify) else {
+        return;
     };
 
+    ui.chat_box.append(&widget);
+
     timeout_add_local_once(Duration::from_millis(1000), move || {
         GLOBAL.with(|global| {
             if let Some(ui) = &*global.borrow() {
@@ -745,16 +948,24 @@ fn make_recv_tick(ctx: Arc<Context>) {
 }
 
 fn run_recv_loop(ctx: Arc<Context>) {
+    thread::spawn({
+        let ctx = ctx.clone();
+        move || loop {
+            thread::sleep(Duration::from_secs(1));
+            ctx.keepalive();
+        }
+    });
+
     thread::spawn(move || loop {
         make_recv_tick(ctx.clone());
 
-        thread::sleep(Duration::from_millis(
-            if ctx.is_focused.load(Ordering::SeqCst) {
+        thread::sleep(ctx.reconnect_delay().unwrap_or_else(|| {
+            Duration::from_millis(if ctx.is_focused.load(Ordering::SeqCst) {
                 ctx.config(|o| o.update_time) as u64
             } else {
                 ctx.config(|o| o.oof_update_time) as u64
-            },
-        ));
+            })
+        }));
     });
 }
 
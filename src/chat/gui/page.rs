@@ -18,17 +18,17 @@ use adw::gtk;
 use gtk::gdk_pixbuf::PixbufAnimation;
 use gtk::pango::WrapMode;
 use gtk::{
-    Align, Box as GtkBox, Button, Calendar, Entry, Fixed, GestureClick, Justification, Label,
-    ListBox, Orientation, Overlay, Picture, ScrolledWindow,
+    Align, Box as GtkBox, Button, Calendar, Entry, Fixed, GestureClick, Image, Justification,
+    Label, ListBox, Orientation, Overlay, Picture, ScrolledWindow,
 };
 
 use crate::chat::{
-    config::get_config_path, ctx::Context, on_send_message, parse_message, SERVER_LIST,
+    config::get_config_path, ctx::Context, on_send_message, ChatMessage, SERVER_LIST,
 };
 
 use super::widgets::CustomLayout;
 use super::{
-    add_chat_messages, build_menu, get_avatar_id, get_message_sign, load_pixbuf, send_notification,
+    add_chat_messages, build_menu, get_avatar_id, load_pixbuf, notify_message, send_notification,
     try_save_config, update_window_title, UiModel,
 };
 
@@ -36,8 +36,9 @@ pub fn get_message_box(
     ctx: Arc<Context>,
     ui: &UiModel,
     message: String,
+    parsed: Option<ChatMessage>,
+    impersonation: bool,
     notify: bool,
-    formatting_enabled: bool,
 ) -> GtkBox {
     // TODO: softcode these colors
 
@@ -49,51 +50,44 @@ pub fn get_message_box(
 
     let mut label = String::new();
 
-    if let (true, Some((date, ip, content, nick, _))) =
-        (formatting_enabled, parse_message(message.clone()))
-    {
-        if let Some(ip) = ip {
+    if let Some(message) = parsed {
+        if let Some(ip) = &message.ip {
             if ctx.config(|o| o.show_other_ip) {
                 label.push_str(&format!(
                     "<span color=\"{ip_color}\">{}</span> ",
-                    glib::markup_escape_text(&ip)
+                    glib::markup_escape_text(ip)
                 ));
             }
         }
 
         label.push_str(&format!(
             "<span color=\"{date_color}\">[{}]</span> ",
-            glib::markup_escape_text(&date)
+            glib::markup_escape_text(&message.local_date(&ctx.config(|o| o.date_format.clone())))
         ));
 
-        if let Some((name, color)) = nick {
+        if let (Some(name), Some(color)) = (&message.author, &message.color) {
             label.push_str(&format!(
                 "<span font_weight=\"bold\" color=\"{}\">&lt;{}&gt;</span> ",
                 color.to_uppercase(),
-                glib::markup_escape_text(&name)
+                glib::markup_escape_text(name)
             ));
 
-            if notify && !ui.window.is_active() {
-                if ctx.config(|o| o.chunked_enabled) {
-                    send_notification(
-                        ctx.clone(),
-                        ui,
-                        &format!("{}'s Message", &name),
-                        &glib::markup_escape_text(&content),
-                    );
-                }
+            if message.verified_author().is_some() {
+                label.push_str(&format!("<span color=\"{date_color}\">✓</span> "));
+            } else if impersonation {
+                label.push_str("<span color=\"#FA7070\">(unverified)</span> ");
             }
-        } else {
-            if notify && !ui.window.is_active() {
-                if ctx.config(|o| o.chunked_enabled) {
-                    send_notification(ctx.clone(), ui, "System Message", &content);
-                }
+        }
+
+        if notify && !ui.window.is_active() {
+            if ctx.chunked_enabled() {
+                notify_message(ctx.clone(), ui, &message);
             }
         }
 
         label.push_str(&format!(
             "<span color=\"{text_color}\">{}</span>",
-            glib::markup_escape_text(&content)
+            glib::markup_escape_text(&message.text)
         ));
     } else {
         label.push_str(&format!(
@@ -102,7 +96,7 @@ pub fn get_message_box(
         ));
 
         if notify && !ui.window.is_active() {
-            if ctx.config(|o| o.chunked_enabled) {
+            if ctx.chunked_enabled() {
                 send_notification(ctx.clone(), ui, "Chat Message", &message);
             }
         }
@@ -162,8 +156,9 @@ pub fn get_new_message_box(
     ctx: Arc<Context>,
     ui: &UiModel,
     message: String,
+    parsed: Option<ChatMessage>,
+    impersonation: bool,
     notify: bool,
-    formatting_enabled: bool,
 ) -> Overlay {
     // TODO: softcode these colors
 
@@ -173,57 +168,28 @@ pub fn get_new_message_box(
         ("#585858", "#292929", "#000000")
     };
 
-    let latest_sign = ui.latest_sign.load(Ordering::SeqCst);
-
-    let (date, ip, content, name, color, avatar, avatar_id) =
-        if let (true, Some((date, ip, content, nick, avatar))) =
-            (formatting_enabled, parse_message(message.clone()))
-        {
-            (
-                date,
-                ip,
-                content,
-                nick.as_ref()
-                    .map(|o| o.0.to_string())
-                    .unwrap_or("System".to_string()),
-                nick.as_ref()
-                    .map(|o| o.1.to_string())
-                    .unwrap_or("#DDDDDD".to_string()),
-                avatar.clone(),
-                avatar.map(|o| get_avatar_id(&o)).unwrap_or_default(),
-            )
-        } else {
-            (
-                Local::now().format("%d.%m.%Y %H:%M").to_string(),
-                None,
-                message,
-                "System".to_string(),
-                "#DDDDDD".to_string(),
-                None,
-                0,
-            )
-        };
+    let message = parsed.unwrap_or_else(|| ChatMessage::system(&message));
+
+    let name = message.author.clone().unwrap_or("System".to_string());
+    let color = message.color.clone().unwrap_or("#DDDDDD".to_string());
+    let avatar = message.avatar.clone();
+    let avatar_id = avatar
+        .as_ref()
+        .map(|o| get_avatar_id(o))
+        .unwrap_or_default();
 
     if notify && !ui.window.is_active() {
-        if ctx.config(|o| o.chunked_enabled) {
-            send_notification(
-                ctx.clone(),
-                ui,
-                &if name == *"System" {
-                    "System Message".to_string()
-                } else {
-                    format!("{}'s Message", name)
-                },
-                &glib::markup_escape_text(&content),
-            );
+        if ctx.chunked_enabled() {
+            notify_message(ctx.clone(), ui, &message);
         }
     }
 
-    let sign = get_message_sign(&name, &date);
-
-    let squashed = latest_sign == sign;
-
-    ui.latest_sign.store(sign, Ordering::SeqCst);
+    let squashed = ui
+        .latest_message
+        .lock()
+        .unwrap()
+        .replace(message.clone())
+        .is_some_and(|o| message.is_grouped_with(&o));
 
     let overlay = Overlay::new();
 
@@ -294,12 +260,14 @@ pub fn get_new_message_box(
     let vbox = GtkBox::new(Orientation::Vertical, 2);
 
     if !squashed {
-        vbox.append(&Label::builder()
+        let header = GtkBox::new(Orientation::Horizontal, 6);
+
+        let sign = Label::builder()
             .label(format!(
-                "<span color=\"{color}\">{}</span> <span color=\"{date_color}\">{}</span> <span color=\"{ip_color}\">{}</span>", 
+                "<span color=\"{color}\">{}</span> <span color=\"{date_color}\">{}</span> <span color=\"{ip_color}\">{}</span>",
                 glib::markup_escape_text(&name),
-                glib::markup_escape_text(&date),
-                glib::markup_escape_text(&ip.unwrap_or_default()),
+                glib::markup_escape_text(&message.local_date(&ctx.config(|o| o.date_format.clone()))),
+                glib::markup_escape_text(&message.ip.clone().unwrap_or_default()),
             ))
             .halign(Align::Start)
             .valign(Align::Start)
@@ -307,14 +275,57 @@ pub fn get_new_message_box(
             .wrap(true)
             .wrap_mode(WrapMode::WordChar)
             .use_markup(true)
-            .build());
+            .build();
+
+        header.append(&sign);
+
+        if message.verified_author().is_some() {
+            let verified = Image::from_icon_name("emblem-ok-symbolic");
+            verified.set_tooltip_text(Some("Verified: sent by a registered user"));
+            header.append(&verified);
+        } else if impersonation {
+            let unverified = Image::from_icon_name("dialog-warning-symbolic");
+            unverified.set_css_classes(&["warning"]);
+            unverified.set_tooltip_text(Some(
+                "Unverified: sent without auth with the name of a registered user",
+            ));
+            header.append(&unverified);
+        }
+
+        if let Some(client) = &message.client {
+            let tooltip = format!("Sent from {client}");
+            sign.set_tooltip_text(Some(&tooltip));
+
+            let badge = GtkBox::new(Orientation::Horizontal, 3);
+            badge.set_css_classes(&["client-badge"]);
+            badge.set_valign(Align::Center);
+            badge.set_tooltip_text(Some(&tooltip));
+
+            if let Some(icon) = ctx.user_agents().get(client).and_then(|o| o.icon.clone()) {
+                badge.append(&Image::from_icon_name(&icon));
+            }
+
+            badge.append(
+                &Label::builder()
+                    .label(format!(
+                        "<span color=\"{color}\">{}</span>",
+                        glib::markup_escape_text(client)
+                    ))
+                    .use_markup(true)
+                    .build(),
+            );
+
+            header.append(&badge);
+        }
+
+        vbox.append(&header);
     }
 
     vbox.append(
         &Label::builder()
             .label(format!(
                 "<span color=\"{text_color}\">{}</span>",
-                glib::markup_escape_text(&content)
+                glib::markup_escape_text(&message.text)
             ))
             .halign(Align::Start)
             .hexpand(true)
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use adw::gdk::Display;
@@ -7,20 +9,121 @@ use adw::prelude::*;
 use adw::Application;
 use libadwaita::gtk::Adjustment;
 use libadwaita::{
-    self as adw, ActionRow, ButtonRow, EntryRow, PreferencesDialog, PreferencesGroup,
-    PreferencesPage, SpinRow, SwitchRow,
+    self as adw, ActionRow, ButtonRow, ComboRow, EntryRow, ExpanderRow, PreferencesDialog,
+    PreferencesGroup, PreferencesPage, SpinRow, SwitchRow,
 };
 
 use adw::gtk;
-use gtk::Button;
+use gtk::{Button, StringList};
 
 use crate::chat::{
-    config::{get_config_path, Config},
+    config::{default_date_format, get_config_path, Config},
     ctx::Context,
+    user_agent::{built_in_user_agents, UserAgent},
 };
+use crate::proto::{Dialect, TlsMode};
 
 use super::{try_save_config, update_window_title};
 
+/// Editor rows of the custom client
+struct UserAgentRows {
+    expander: ExpanderRow,
+    name: EntryRow,
+    marker: EntryRow,
+    regex: EntryRow,
+    color: EntryRow,
+    icon: EntryRow,
+}
+
+impl UserAgentRows {
+    fn new(agent: &UserAgent) -> UserAgentRows {
+        let expander = ExpanderRow::builder()
+            .title(glib::markup_escape_text(&agent.name))
+            .subtitle(glib::markup_escape_text(&agent.pattern()))
+            .build();
+
+        let name = EntryRow::builder()
+            .title("Name")
+            .text(agent.name.as_str())
+            .build();
+        let marker = EntryRow::builder()
+            .title("Marker (magic key before the name)")
+            .text(agent.marker.as_str())
+            .build();
+        let regex = EntryRow::builder()
+            .title("Regex (with name and text groups, empty for marker<name> text)")
+            .text(agent.regex.clone().unwrap_or_default())
+            .build();
+        let color = EntryRow::builder()
+            .title("Color (like #70fa7a)")
+            .text(agent.color.as_str())
+            .build();
+        let icon = EntryRow::builder()
+            .title("Icon name")
+            .text(agent.icon.clone().unwrap_or_default())
+            .build();
+
+        for row in [&name, &marker, &regex, &color, &icon] {
+            expander.add_row(row);
+        }
+
+        UserAgentRows {
+            expander,
+            name,
+            marker,
+            regex,
+            color,
+            icon,
+        }
+    }
+
+    /// Edited client, None if it has no name
+    fn user_agent(&self) -> Option<UserAgent> {
+        let text = |row: &EntryRow| Some(row.text().trim().to_string()).filter(|o| !o.is_empty());
+
+        Some(UserAgent {
+            name: text(&self.name)?,
+            marker: text(&self.marker).unwrap_or_default(),
+            regex: text(&self.regex),
+            color: text(&self.color).unwrap_or("#DDDDDD".to_string()),
+            icon: text(&self.icon),
+        })
+    }
+}
+
+/// Add custom client editor to the group
+fn add_user_agent_rows(
+    group: &PreferencesGroup,
+    rows: Rc<RefCell<Vec<UserAgentRows>>>,
+    agent: &UserAgent,
+) {
+    let agent_rows = UserAgentRows::new(agent);
+
+    let remove_button = Button::from_icon_name("user-trash-symbolic");
+    remove_button.set_valign(gtk::Align::Center);
+    remove_button.set_css_classes(&["flat"]);
+
+    let expander = agent_rows.expander.clone();
+
+    remove_button.connect_clicked(clone!(
+        #[weak]
+        group,
+        #[weak]
+        expander,
+        #[strong]
+        rows,
+        move |_| {
+            group.remove(&expander);
+            rows.borrow_mut().retain(|o| o.expander != expander);
+        }
+    ));
+
+    agent_rows.expander.add_suffix(&remove_button);
+
+    group.add(&agent_rows.expander);
+    rows.borrow_mut().push(agent_rows);
+}
+
 pub fn open_settings(ctx: Arc<Context>, app: &Application) {
     let dialog = PreferencesDialog::builder().build();
 
@@ -196,12 +299,34 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
     // Proxy preference
 
     let proxy = EntryRow::builder()
-        .title("Socks proxy")
+        .title("Proxy (socks5://, socks5h://, http:// or https://)")
         .text(ctx.config(|o| o.proxy.clone()).unwrap_or_default())
         .build();
 
     group.add(&proxy);
 
+    // TLS verification preference
+
+    let tls_modes = [TlsMode::Strict, TlsMode::Tofu, TlsMode::Insecure];
+
+    let tls_mode = ComboRow::builder()
+        .title("Certificate verification")
+        .subtitle("For racs:// and wracs:// servers")
+        .model(&StringList::new(&[
+            "Strict",
+            "Trust on first use",
+            "Insecure",
+        ]))
+        .selected(
+            tls_modes
+                .iter()
+                .position(|o| *o == ctx.config(|o| o.tls_mode))
+                .unwrap_or_default() as u32,
+        )
+        .build();
+
+    group.add(&tls_mode);
+
     // Max avatar size preference
 
     let max_avatar_size = SpinRow::builder()
@@ -220,6 +345,186 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
 
     group.add(&max_avatar_size);
 
+    // Connect timeout preference
+
+    let connect_timeout = SpinRow::builder()
+        .title("Connect timeout")
+        .subtitle("In milliseconds")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(100.0)
+                .upper(1048576.0)
+                .page_increment(1000.0)
+                .step_increment(1000.0)
+                .value(ctx.config(|o| o.connect_timeout) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&connect_timeout);
+
+    // Read timeout preference
+
+    let read_timeout = SpinRow::builder()
+        .title("Read timeout")
+        .subtitle("In milliseconds")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(100.0)
+                .upper(1048576.0)
+                .page_increment(1000.0)
+                .step_increment(1000.0)
+                .value(ctx.config(|o| o.read_timeout) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&read_timeout);
+
+    // Write timeout preference
+
+    let write_timeout = SpinRow::builder()
+        .title("Write timeout")
+        .subtitle("In milliseconds")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(100.0)
+                .upper(1048576.0)
+                .page_increment(1000.0)
+                .step_increment(1000.0)
+                .value(ctx.config(|o| o.write_timeout) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&write_timeout);
+
+    // Max frame size preference
+
+    let max_frame_size = SpinRow::builder()
+        .title("Max WebSocket frame size")
+        .subtitle("In bytes, 0 is unlimited")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(0.0)
+                .upper(4294967296.0)
+                .page_increment(1048576.0)
+                .step_increment(1024.0)
+                .value(ctx.config(|o| o.max_frame_size) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&max_frame_size);
+
+    // Max message size preference
+
+    let max_message_size = SpinRow::builder()
+        .title("Max WebSocket message size")
+        .subtitle("In bytes, 0 is unlimited")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(0.0)
+                .upper(4294967296.0)
+                .page_increment(1048576.0)
+                .step_increment(1024.0)
+                .value(ctx.config(|o| o.max_message_size) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&max_message_size);
+
+    // Ping interval preference
+
+    let ping_interval = SpinRow::builder()
+        .title("WebSocket ping interval")
+        .subtitle("In milliseconds, keeps idle WRAC connection alive, 0 is disabled")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(0.0)
+                .upper(3600000.0)
+                .page_increment(10000.0)
+                .step_increment(1000.0)
+                .value(ctx.config(|o| o.ping_interval) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&ping_interval);
+
+    // History page size preference
+
+    let history_page_size = SpinRow::builder()
+        .title("History page size")
+        .subtitle("In bytes, older messages loaded when scrolled to the top")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(256.0)
+                .upper(16777216.0)
+                .page_increment(8192.0)
+                .step_increment(1024.0)
+                .value(ctx.config(|o| o.history_page_size) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&history_page_size);
+
+    // Reconnect delay preference
+
+    let reconnect_delay = SpinRow::builder()
+        .title("Reconnect delay")
+        .subtitle("In milliseconds, doubles after every failed attempt")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(100.0)
+                .upper(1048576.0)
+                .page_increment(1000.0)
+                .step_increment(100.0)
+                .value(ctx.config(|o| o.reconnect_delay) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&reconnect_delay);
+
+    // Max reconnect delay preference
+
+    let max_reconnect_delay = SpinRow::builder()
+        .title("Max reconnect delay")
+        .subtitle("In milliseconds")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(100.0)
+                .upper(1048576.0)
+                .page_increment(10000.0)
+                .step_increment(1000.0)
+                .value(ctx.config(|o| o.max_reconnect_delay) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&max_reconnect_delay);
+
+    // Reconnect attempts preference
+
+    let reconnect_attempts = SpinRow::builder()
+        .title("Reconnect attempts")
+        .subtitle("Server is shown as offline after them")
+        .adjustment(
+            &Adjustment::builder()
+                .lower(0.0)
+                .upper(1000.0)
+                .page_increment(5.0)
+                .step_increment(1.0)
+                .value(ctx.config(|o| o.reconnect_attempts) as f64)
+                .build(),
+        )
+        .build();
+
+    group.add(&reconnect_attempts);
+
     page.add(&group);
 
     let group = PreferencesGroup::builder()
@@ -227,6 +532,74 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
         .description("Rac protocol preferences")
         .build();
 
+    // Dialect preference
+
+    let dialect = ComboRow::builder()
+        .title("Server dialect")
+        .subtitle("Protocol flavour of the current server")
+        .model(&StringList::new(
+            &[&["Auto-detect"][..], &Dialect::ALL.map(|o| o.name())].concat(),
+        ))
+        .selected(
+            ctx.config(|o| o.dialect())
+                .and_then(|d| Dialect::ALL.iter().position(|o| *o == d))
+                .map(|o| o as u32 + 1)
+                .unwrap_or_default(),
+        )
+        .build();
+
+    group.add(&dialect);
+
+    // Server auth preference
+
+    let server_auth = ComboRow::builder()
+        .title("Server auth")
+        .subtitle("Does the current server support auth (sender names are verified only then)")
+        .model(&StringList::new(&[
+            "Auto-detect",
+            "Supported",
+            "Not supported",
+        ]))
+        .selected(match ctx.config(|o| o.server_auth()) {
+            None => 0,
+            Some(true) => 1,
+            Some(false) => 2,
+        })
+        .build();
+
+    group.add(&server_auth);
+
+    // Server timezone preference
+
+    let server_timezone = EntryRow::builder()
+        .title("Server timezone (like +03:00, empty for local)")
+        .text(
+            ctx.config(|o| o.timezones.get(&o.host).cloned())
+                .unwrap_or_default(),
+        )
+        .build();
+
+    group.add(&server_timezone);
+
+    // Server proxy preference
+
+    let server_proxy = EntryRow::builder()
+        .title("Server proxy chain (comma separated, \"direct\" for no proxy)")
+        .text(
+            ctx.config(|o| o.proxies.get(&o.host).cloned())
+                .map(|o| {
+                    if o.is_empty() {
+                        "direct".to_string()
+                    } else {
+                        o.join(", ")
+                    }
+                })
+                .unwrap_or_default(),
+        )
+        .build();
+
+    group.add(&server_proxy);
+
     // Message format preference
 
     let message_format = EntryRow::builder()
@@ -258,6 +631,26 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
 
     group.add(&chunked_reading);
 
+    // Probe server preference
+
+    let probe_server = SwitchRow::builder()
+        .title("Detect server capabilities")
+        .subtitle("Check chunked reading and auth support on connect (overrides chunked reading)")
+        .active(ctx.config(|o| o.probe_enabled))
+        .build();
+
+    group.add(&probe_server);
+
+    // Probe auth preference
+
+    let probe_auth = SwitchRow::builder()
+        .title("Check auth support")
+        .subtitle("Send a message from a non-existent user when detecting server capabilities (may be shown in the chat)")
+        .active(ctx.config(|o| o.probe_auth))
+        .build();
+
+    group.add(&probe_auth);
+
     // Enable commands preference
 
     let enable_commands = SwitchRow::builder()
@@ -292,6 +685,19 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
 
     group.add(&debug_logs);
 
+    // Record sessions preference
+
+    let record_sessions = SwitchRow::builder()
+        .title("Record sessions")
+        .subtitle(format!(
+            "Write all protocol data to {}",
+            ctx.config(|o| o.capture_path()).display()
+        ))
+        .active(ctx.config(|o| o.record_enabled))
+        .build();
+
+    group.add(&record_sessions);
+
     // Show IPs preference
 
     let show_ips = SwitchRow::builder()
@@ -312,6 +718,15 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
 
     group.add(&format_messages);
 
+    // Date format preference
+
+    let date_format = EntryRow::builder()
+        .title("Date format (like %d.%m.%Y %H:%M)")
+        .text(ctx.config(|o| o.date_format.clone()))
+        .build();
+
+    group.add(&date_format);
+
     // Show avatars preference
 
     let show_avatars = SwitchRow::builder()
@@ -369,6 +784,98 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
 
     dialog.add(&page);
 
+    let page = PreferencesPage::builder()
+        .title("Clients")
+        .icon_name("system-users-symbolic")
+        .build();
+
+    let group = PreferencesGroup::builder()
+        .title("Custom clients")
+        .description("Recognized by the message text before the built-in ones")
+        .build();
+
+    // Custom clients preference
+
+    let user_agents = Rc::new(RefCell::new(Vec::new()));
+
+    for agent in ctx.config(|o| o.user_agents.clone()) {
+        add_user_agent_rows(&group, user_agents.clone(), &agent);
+    }
+
+    let add_button = ButtonRow::builder().title("Add client").build();
+
+    add_button.connect_activated(clone!(
+        #[weak]
+        group,
+        #[strong]
+        user_agents,
+        move |_| {
+            add_user_agent_rows(
+                &group,
+                user_agents.clone(),
+                &UserAgent::new("New client", "", "#DDDDDD"),
+            );
+        }
+    ));
+
+    page.add(&group);
+
+    // add button stays under the clients
+    let group = PreferencesGroup::new();
+    group.add(&add_button);
+    page.add(&group);
+
+    let group = PreferencesGroup::builder()
+        .title("Shown clients")
+        .description("Messages from the disabled clients are hidden")
+        .build();
+
+    // Shown clients preference
+
+    let shown_clients: Vec<(String, SwitchRow)> = ctx
+        .user_agents()
+        .agents()
+        .iter()
+        .map(|agent| {
+            let row = SwitchRow::builder()
+                .title(glib::markup_escape_text(&agent.name))
+                .active(
+                    !ctx.config(|o| o.hidden_clients.clone())
+                        .contains(&agent.name),
+                )
+                .build();
+
+            group.add(&row);
+
+            (agent.name.clone(), row)
+        })
+        .collect();
+
+    page.add(&group);
+
+    let group = PreferencesGroup::builder()
+        .title("Built-in clients")
+        .description("Custom client with the same name replaces the built-in one")
+        .build();
+
+    for agent in built_in_user_agents() {
+        group.add(
+            &ActionRow::builder()
+                .title(glib::markup_escape_text(&agent.name))
+                .subtitle(glib::markup_escape_text(&format!(
+                    "{}  {}",
+                    agent.pattern(),
+                    agent.color
+                )))
+                .css_classes(["property"])
+                .build(),
+        );
+    }
+
+    page.add(&group);
+
+    dialog.add(&page);
+
     dialog.connect_closed(move |_| {
         let old_config = ctx.config.read().unwrap().clone();
 
@@ -398,15 +905,95 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
             konata_size: konata_size.value() as usize,
             max_messages: messages_limit.value() as usize,
             max_avatar_size: max_avatar_size.value() as u64,
+            connect_timeout: connect_timeout.value() as usize,
+            read_timeout: read_timeout.value() as usize,
+            write_timeout: write_timeout.value() as usize,
+            max_frame_size: max_frame_size.value() as usize,
+            max_message_size: max_message_size.value() as usize,
+            wrac_headers: old_config.wrac_headers.clone(),
+            ping_interval: ping_interval.value() as usize,
+            dialects: {
+                let mut dialects = old_config.dialects.clone();
+                let host = host.text().to_string();
+
+                match (dialect.selected() as usize)
+                    .checked_sub(1)
+                    .and_then(|o| Dialect::ALL.get(o))
+                {
+                    Some(dialect) => dialects.insert(host, *dialect),
+                    None => dialects.remove(&host),
+                };
+
+                dialects
+            },
+            auth_support: {
+                let mut auth_support = old_config.auth_support.clone();
+                let host = host.text().to_string();
+
+                match server_auth.selected() {
+                    1 => auth_support.insert(host, true),
+                    2 => auth_support.insert(host, false),
+                    _ => auth_support.remove(&host),
+                };
+
+                auth_support
+            },
+            timezones: {
+                let mut timezones = old_config.timezones.clone();
+                let host = host.text().to_string();
+                let timezone = server_timezone.text().trim().to_string();
+
+                if timezone.is_empty() {
+                    timezones.remove(&host);
+                } else {
+                    timezones.insert(host, timezone);
+                }
+
+                timezones
+            },
+            user_agents: user_agents
+                .borrow()
+                .iter()
+                .filter_map(|o| o.user_agent())
+                .collect(),
+            hidden_clients: old_config
+                .hidden_clients
+                .iter()
+                .filter(|o| !shown_clients.iter().any(|(name, _)| name == *o))
+                .cloned()
+                .chain(
+                    shown_clients
+                        .iter()
+                        .filter(|(_, row)| !row.is_active())
+                        .map(|(name, _)| name.clone()),
+                )
+                .collect(),
+            date_format: {
+                let date_format = date_format.text().to_string();
+
+                if date_format.is_empty() {
+                    default_date_format()
+                } else {
+                    date_format
+                }
+            },
+            history_page_size: history_page_size.value() as usize,
+            reconnect_delay: reconnect_delay.value() as usize,
+            max_reconnect_delay: max_reconnect_delay.value() as usize,
+            reconnect_attempts: reconnect_attempts.value() as usize,
             hide_my_ip: hide_my_ip.is_active(),
             remove_gui_shit: remove_gui_shit.is_active(),
             show_other_ip: show_ips.is_active(),
             chunked_enabled: chunked_reading.is_active(),
+            probe_enabled: probe_server.is_active(),
+            probe_auth: probe_auth.is_active(),
             formatting_enabled: format_messages.is_active(),
             commands_enabled: enable_commands.is_active(),
             notifications_enabled: enable_notifications.is_active(),
             new_ui_enabled: show_avatars.is_active(),
             debug_logs: debug_logs.is_active(),
+            record_enabled: record_sessions.is_active(),
+            record_path: old_config.record_path.clone(),
             proxy: {
                 let proxy = proxy.text().to_string();
 
@@ -416,6 +1003,32 @@ pub fn open_settings(ctx: Arc<Context>, app: &Application) {
                     Some(proxy)
                 }
             },
+            proxies: {
+                let mut proxies = old_config.proxies.clone();
+                let host = host.text().to_string();
+                let chain = server_proxy.text().trim().to_string();
+
+                if chain.is_empty() {
+                    proxies.remove(&host);
+                } else if chain == "direct" {
+                    proxies.insert(host, Vec::new());
+                } else {
+                    proxies.insert(
+                        host,
+                        chain
+                            .split(',')
+                            .map(|o| o.trim().to_string())
+                            .filter(|o| !o.is_empty())
+                            .collect(),
+                    );
+                }
+
+                proxies
+            },
+            tls_mode: tls_modes
+                .get(tls_mode.selected() as usize)
+                .copied()
+                .unwrap_or(old_config.tls_mode),
             servers: old_config.servers,
         };
         ctx.set_config(&config);
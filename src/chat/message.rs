@@ -0,0 +1,263 @@
+use std::{collections::HashSet, fmt::Write};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+
+use crate::proto::Dialect;
+
+use super::{sanitize_message, UserAgents, AVATAR_REGEX, DEFAULT_USER_AGENT};
+
+/// Date format used by RAC servers, also the default display format
+pub const DATE_FORMAT: &str = "%d.%m.%Y %H:%M";
+
+/// Messages of the same author within this time (in seconds) are grouped
+pub const GROUP_WINDOW: i64 = 20 * 60;
+
+/// Parse timezone offset
+///
+/// timezone - `UTC`, `Z`, `+03:00`, `-0500`, `UTC+3` and so on
+///
+/// returns None if it is not an offset
+pub fn parse_timezone(timezone: &str) -> Option<FixedOffset> {
+    let timezone = timezone.trim();
+    let offset = timezone
+        .strip_prefix("UTC")
+        .or_else(|| timezone.strip_prefix("GMT"))
+        .unwrap_or(timezone);
+
+    if offset.is_empty() || offset == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+
+    let (sign, offset) = match offset.split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+
+    let (hours, minutes) = match offset.split_once(':') {
+        Some(o) => o,
+        None if offset.len() > 2 => offset.split_at_checked(offset.len() - 2)?,
+        None => (offset, "0"),
+    };
+
+    let hours: i32 = hours.parse().ok().filter(|o| *o <= 14)?;
+    let minutes: i32 = minutes.parse().ok().filter(|o| *o < 60)?;
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Attach timezone to the server date
+///
+/// timezone - server timezone, None is the local one
+fn server_time(
+    time: NaiveDateTime,
+    timezone: Option<FixedOffset>,
+) -> Option<DateTime<FixedOffset>> {
+    match timezone {
+        Some(timezone) => timezone.from_local_datetime(&time).single(),
+        None => Local
+            .from_local_datetime(&time)
+            .earliest()
+            .map(|o| o.fixed_offset()),
+    }
+}
+
+/// Was the message sent with auth
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthStatus {
+    /// Server is not known to have auth, or the message has neither the mark nor the server name
+    #[default]
+    Unknown,
+    /// Server added the name before the message, it was sent with auth
+    Authenticated,
+    /// Server marked the message as sent without auth
+    Unauthenticated,
+}
+
+/// Parsed chat message
+///
+/// Shared by the message parser, the GUI and the notifications
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// Message line as the server sent it
+    pub raw: String,
+    /// Date as the server sent it
+    pub date: String,
+    /// Parsed date with the server timezone (None if the dialect doesn't know the format)
+    pub time: Option<DateTime<FixedOffset>>,
+    /// Sender ip (if the dialect has it and server shows it)
+    pub ip: Option<String>,
+    /// Sender name (None for system messages)
+    pub author: Option<String>,
+    /// Name of the client the message was sent from
+    pub client: Option<String>,
+    /// Name color of the client
+    pub color: Option<String>,
+    /// Was the message sent with auth
+    pub auth: AuthStatus,
+    /// Avatar URL
+    pub avatar: Option<String>,
+    /// Message text (without the user agent and name)
+    pub text: String,
+}
+
+impl ChatMessage {
+    /// Parse message line
+    ///
+    /// raw - message line as the server sent it
+    /// dialect - server dialect, that defines the date format, ip and auth marks
+    /// timezone - server timezone, None is the local one
+    /// user_agents - clients to recognize
+    /// auth - server is known to have auth (probed or set by the user), otherwise auth status is unknown,
+    /// because anyone can write the name and the mark without it
+    ///
+    /// returns None if the line is empty or has no date
+    pub fn parse(
+        raw: &str,
+        dialect: Dialect,
+        timezone: Option<FixedOffset>,
+        user_agents: &UserAgents,
+        auth: bool,
+    ) -> Option<ChatMessage> {
+        if raw.is_empty() {
+            return None;
+        }
+
+        let (message, avatar) = if let Some(message) = AVATAR_REGEX.captures(raw) {
+            (
+                message.get(1)?.as_str().to_string(),
+                Some(message.get(2)?.as_str().to_string()),
+            )
+        } else {
+            (raw.to_string(), None)
+        };
+
+        let message = sanitize_message(message)?;
+
+        let line = dialect.parse_line(&message)?;
+        let text = line.text.trim().to_string();
+
+        // in auth mode server adds `<name> ` before the message, that name is checked by password,
+        // any other name is written by the sender, so it is not verified
+        let auth = auth && dialect.supports_auth();
+
+        let server_name = DEFAULT_USER_AGENT
+            .captures(&text)
+            .filter(|o| o.get(0).is_some_and(|o| o.start() == 0))
+            .filter(|_| auth && !line.unauthenticated)
+            .map(|o| (o[1].to_string(), o[2].to_string()));
+
+        let auth = if !auth {
+            AuthStatus::Unknown
+        } else if line.unauthenticated {
+            AuthStatus::Unauthenticated
+        } else if server_name.is_some() {
+            AuthStatus::Authenticated
+        } else {
+            AuthStatus::Unknown
+        };
+
+        let (text, author, client) = match server_name {
+            Some((nick, text)) => match user_agents.parse(&text) {
+                Some((_, text, client)) => (text, Some(nick), Some(client)),
+                None => (text, Some(nick), None),
+            },
+            None => match user_agents.parse(&text) {
+                Some((nick, text, client)) => (text, Some(nick), Some(client)),
+                None => (text, None, None),
+            },
+        };
+
+        Some(ChatMessage {
+            raw: raw.to_string(),
+            time: dialect
+                .parse_date(&line.date)
+                .and_then(|o| server_time(o, timezone)),
+            date: line.date,
+            ip: line.ip,
+            author,
+            client: client.map(|o| o.name.clone()),
+            color: client.map(|o| o.color.clone()),
+            auth,
+            avatar,
+            text,
+        })
+    }
+
+    /// Message that is not from the server or could not be parsed
+    ///
+    /// text - message text
+    ///
+    /// returns message without author, dated now
+    pub fn system(text: &str) -> ChatMessage {
+        let now = Local::now();
+
+        ChatMessage {
+            raw: text.to_string(),
+            date: now.format(DATE_FORMAT).to_string(),
+            time: Some(now.fixed_offset()),
+            ip: None,
+            author: None,
+            client: None,
+            color: None,
+            auth: AuthStatus::Unknown,
+            avatar: None,
+            text: text.to_string(),
+        }
+    }
+
+    /// Date in the local timezone
+    ///
+    /// format - chrono strftime format
+    ///
+    /// returns the date as the server sent it, if it wasn't parsed or the format is invalid
+    pub fn local_date(&self, format: &str) -> String {
+        let mut date = String::new();
+
+        match self.time {
+            Some(time) if write!(date, "{}", time.with_timezone(&Local).format(format)).is_ok() => {
+                date
+            }
+            _ => self.date.clone(),
+        }
+    }
+
+    /// Sender name, if the server checked the password
+    pub fn verified_author(&self) -> Option<&str> {
+        match self.auth {
+            AuthStatus::Authenticated => self.author.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Is the message sent without auth with the name of a registered user
+    ///
+    /// verified - names of the senders of authenticated messages
+    pub fn is_impersonation(&self, verified: &HashSet<String>) -> bool {
+        match (&self.auth, &self.author) {
+            (AuthStatus::Unauthenticated, Some(author)) => verified.contains(author),
+            _ => false,
+        }
+    }
+
+    /// Is the message a continuation of the previous one
+    ///
+    /// returns true if the author, client and auth status are the same
+    /// and the message was sent within [`GROUP_WINDOW`]
+    pub fn is_grouped_with(&self, previous: &ChatMessage) -> bool {
+        if self.author != previous.author
+            || self.client != previous.client
+            || self.auth != previous.auth
+        {
+            return false;
+        }
+
+        match (self.time, previous.time) {
+            (Some(time), Some(previous)) => {
+                (0..GROUP_WINDOW).contains(&(time - previous).num_seconds())
+            }
+            _ => self.date == previous.date,
+        }
+    }
+}
@@ -4,10 +4,15 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::connect_rac;
+use super::proto::{
+    read_messages, read_messages_data, register_user, send_message, send_message_auth, AuthResult,
+    Dialect, RegisterResult,
+};
 
-use super::proto::{connect, read_messages, register_user, send_message, send_message_auth};
+#[cfg(feature = "gtk")]
+use super::proto::Error as ProtoError;
 
+use chrono::FixedOffset;
 use lazy_static::lazy_static;
 use regex::Regex;
 
@@ -18,7 +23,7 @@ pub mod gui;
 #[cfg(feature = "gtk")]
 pub use gui::run_main_loop;
 #[cfg(feature = "gtk")]
-use gui::{add_chat_messages, clear_chat_messages};
+use gui::{add_chat_messages, clear_chat_messages, show_pin_mismatch};
 
 const HELP_MESSAGE: &str = "Help message:
 /help - show help message
@@ -31,23 +36,8 @@ const HELP_MESSAGE: &str = "Help message:
 lazy_static! {
     static ref ANSI_REGEX: Regex = Regex::new(r"\x1B(?:[@-Z\\-_]|\[[0-?]*[ -/]*[@-~])").unwrap();
     static ref CONTROL_CHARS_REGEX: Regex = Regex::new(r"[\x00-\x1F\x7F]").unwrap();
-
-    pub static ref DATE_REGEX: Regex = Regex::new(r"\[(.*?)\] (.*)").unwrap();
-    pub static ref IP_REGEX: Regex = Regex::new(r"\{(.*?)\} (.*)").unwrap();
     pub static ref AVATAR_REGEX: Regex = Regex::new(r"(.*)\x06!!AR!!(.*)").unwrap();
-
     pub static ref DEFAULT_USER_AGENT: Regex = Regex::new(r"<(.*?)> (.*)").unwrap();
-
-    pub static ref USER_AGENTS: Vec<(Regex, String)> = vec![
-        (Regex::new(r"\u{B9AC}\u{3E70}<(.*?)> (.*)").unwrap(),         "#70fa7a".to_string()),     // bRAC
-        (Regex::new(r"\u{2550}\u{2550}\u{2550}<(.*?)> (.*)").unwrap(), "#fa7070".to_string()),     // CRAB
-        (Regex::new(r"\u{00B0}\u{0298}<(.*?)> (.*)").unwrap(),         "#da70fa".to_string()),     // Mefidroniy
-        (Regex::new(r"\u{2042}<(.*?)> (.*)").unwrap(),                 "#f8b91b".to_string()),     // cRACk
-        (Regex::new(r"\u{0D9E}<(.*?)> (.*)").unwrap(),                 "#aeff00".to_string()),     // Snowdrop
-        (Regex::new(r"\u{30C4}<(.*?)> (.*)").unwrap(),                 "#ff5733".to_string()),     // Crack
-        (Regex::new(r"<(.*?)> (.*)").unwrap(),                         "#70fadc".to_string()),     // clRAC
-    ];
-
     pub static ref SERVER_LIST: Vec<String> = vec![
         "wracs://meex.lol:11234".to_string(),
         "rac://meex.lol".to_string(),
@@ -57,7 +47,13 @@ lazy_static! {
 }
 
 pub mod config;
+pub mod connection;
 pub mod ctx;
+pub mod message;
+pub mod user_agent;
+
+pub use message::{AuthStatus, ChatMessage};
+pub use user_agent::{UserAgent, UserAgents};
 
 pub fn sanitize_text(input: &str) -> String {
     let without_ansi = ANSI_REGEX.replace_all(input, "");
@@ -85,7 +81,7 @@ pub fn on_command(ctx: Arc<Context>, command: &str) -> Result<(), Box<dyn Error>
         };
         let times = times.parse()?;
         for _ in 0..times {
-            send_message(connect_rac!(ctx), "\r")?;
+            ctx.with_connection(|o| send_message(o, "\r"))?;
         }
     } else if command == "spam" {
         let Some(times) = args.get(0) else {
@@ -94,7 +90,7 @@ pub fn on_command(ctx: Arc<Context>, command: &str) -> Result<(), Box<dyn Error>
         let times = times.parse()?;
         let msg = args[1..].join(" ");
         for _ in 0..times {
-            send_message(connect_rac!(ctx), &("\r".to_string() + &msg))?;
+            ctx.with_connection(|o| send_message(o, &("\r".to_string() + &msg)))?;
         }
     } else if command == "help" {
         add_message(ctx.clone(), HELP_MESSAGE)?;
@@ -104,12 +100,18 @@ pub fn on_command(ctx: Arc<Context>, command: &str) -> Result<(), Box<dyn Error>
             return Ok(());
         };
 
-        match register_user(connect_rac!(ctx), &ctx.name(), pass) {
-            Ok(true) => {
+        match ctx.with_connection(|o| register_user(o, &ctx.name(), pass, ctx.dialect())) {
+            Ok(RegisterResult::Registered) => {
                 add_message(ctx.clone(), "you was registered successfully bro")?;
                 *ctx.registered.write().unwrap() = Some(pass.to_string());
             }
-            Ok(false) => add_message(ctx.clone(), "user with this account already exists bruh")?,
+            Ok(RegisterResult::NameTaken) => {
+                add_message(ctx.clone(), "user with this account already exists bruh")?
+            }
+            Ok(RegisterResult::Unknown(code)) => add_message(
+                ctx.clone(),
+                &format!("server returned unknown registration code: {code}"),
+            )?,
             Err(e) => add_message(ctx.clone(), &format!("ERROR while registrationing: {}", e))?,
         };
     } else if command == "login" {
@@ -127,19 +129,27 @@ pub fn on_command(ctx: Arc<Context>, command: &str) -> Result<(), Box<dyn Error>
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
         );
 
-        send_message(connect_rac!(ctx), &message)?;
+        ctx.with_connection(|o| send_message(o, &message))?;
 
         let start = SystemTime::now();
 
         loop {
-            let data = read_messages(
-                connect_rac!(ctx),
-                ctx.config(|o| o.max_messages),
-                before,
-                ctx.config(|o| o.chunked_enabled),
-            )
-            .ok()
-            .flatten();
+            let data = match ctx.with_connection(|o| {
+                read_messages(
+                    o,
+                    ctx.config(|o| o.max_messages),
+                    before,
+                    ctx.chunked_enabled(),
+                    ctx.dialect(),
+                )
+            }) {
+                Ok(data) => data,
+                Err(ProtoError::HistoryReset { .. }) => {
+                    before = 0;
+                    None
+                }
+                Err(_) => None,
+            };
 
             if let Some((data, size)) = data {
                 if let Some(last) = data.iter().rev().find(|o| o.contains(&message)) {
@@ -165,6 +175,21 @@ pub fn on_command(ctx: Arc<Context>, command: &str) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
+/// Message format for auth mode (read docs/auth_mode.md)
+///
+/// format - message format, its magic key (the part before the name) is kept
+///
+/// returns `{magic key}<{name}> {text}`
+pub fn auth_message_format(format: &str) -> String {
+    let marker = format
+        .split_once("{name}")
+        .map(|o| o.0.trim_end_matches('<'))
+        .filter(|o| !o.contains("{text}"))
+        .unwrap_or_default();
+
+    format!("{marker}<{{name}}> {{text}}")
+}
+
 pub fn prepare_message(ctx: Arc<Context>, message: &str) -> String {
     format!(
         "{}{}{}",
@@ -195,27 +220,49 @@ pub fn print_message(ctx: Arc<Context>, message: String) -> Result<(), Box<dyn E
 
 #[cfg(feature = "gtk")]
 pub fn recv_tick(ctx: Arc<Context>) -> Result<(), Box<dyn Error>> {
+    ctx.probe_if_needed();
+
     let last_size = ctx.packet_size();
 
-    match read_messages(
-        connect_rac!(ctx),
-        ctx.config(|o| o.max_messages),
-        ctx.packet_size(),
-        ctx.config(|o| o.chunked_enabled),
-    ) {
-        Ok(Some((messages, size))) => {
-            if ctx.config(|o| o.chunked_enabled) {
-                ctx.add_messages_packet(ctx.config(|o| o.max_messages), messages.clone(), size);
+    match ctx.with_connection(|o| {
+        read_messages_data(o, ctx.packet_size(), ctx.chunked_enabled(), ctx.dialect())
+    }) {
+        Ok(Some((data, size))) => {
+            let max_messages = ctx.config(|o| o.max_messages);
+            let messages = ctx.dialect().parse_messages(&data, max_messages);
+
+            ctx.detect_dialect(&messages);
+
+            if ctx.chunked_enabled() {
+                // offsets are counted in the raw data, the lines may be changed by the dialect
+                let start = ctx.dialect().messages_start(&data, max_messages);
+                ctx.add_messages_packet(max_messages, messages.clone(), size, start);
                 if last_size == 0 {
                     clear_chat_messages(ctx.clone(), messages);
                 } else {
                     add_chat_messages(ctx.clone(), messages);
                 }
             } else {
-                ctx.put_messages_packet(ctx.config(|o| o.max_messages), messages.clone(), size);
+                ctx.put_messages_packet(max_messages, messages.clone(), size);
                 clear_chat_messages(ctx.clone(), messages);
             }
         }
+        Err(ProtoError::PinMismatch {
+            host,
+            expected,
+            actual,
+        }) => {
+            show_pin_mismatch(ctx.clone(), host, expected, actual);
+        }
+        Err(e @ ProtoError::HistoryReset { .. }) => {
+            if ctx.config(|o| o.debug_logs) {
+                add_chat_messages(ctx.clone(), vec![format!("Read messages: {e}, reloading")]);
+            }
+
+            // full read is never chunked, so it can't be reset again
+            ctx.reset_history();
+            return recv_tick(ctx);
+        }
         Err(e) => {
             if ctx.config(|o| o.debug_logs) {
                 add_chat_messages(
@@ -235,9 +282,23 @@ pub fn on_send_message(ctx: Arc<Context>, message: &str) -> Result<(), Box<dyn E
     if message.starts_with("/") && ctx.config(|o| o.commands_enabled) {
         on_command(ctx.clone(), &message)?;
     } else {
+        let password = ctx
+            .registered
+            .read()
+            .unwrap()
+            .clone()
+            .filter(|_| ctx.dialect().supports_auth());
+
+        let format = ctx.config(|o| o.message_format.clone());
+        let format = if password.is_some() {
+            auth_message_format(&format)
+        } else {
+            format
+        };
+
         let mut message = prepare_message(
             ctx.clone(),
-            &ctx.config(|o| o.message_format.clone())
+            &format
                 .replace("{name}", &ctx.name())
                 .replace("{text}", &message),
         );
@@ -246,10 +307,26 @@ pub fn on_send_message(ctx: Arc<Context>, message: &str) -> Result<(), Box<dyn E
             message = format!("{message}\x06!!AR!!{avatar}"); // TODO: softcode this shittttttt
         }
 
-        if let Some(password) = ctx.registered.read().unwrap().clone() {
-            send_message_auth(connect_rac!(ctx), &ctx.name(), &password, &message)?;
+        if let Some(password) = password {
+            match ctx.with_connection(|o| {
+                send_message_auth(o, &ctx.name(), &password, &message, ctx.dialect())
+            })? {
+                AuthResult::Sent => {}
+                AuthResult::UserNotFound => add_message(
+                    ctx.clone(),
+                    "message was not sent: user does not exist, try /register",
+                )?,
+                AuthResult::WrongPassword => add_message(
+                    ctx.clone(),
+                    "message was not sent: incorrect password, try /login again",
+                )?,
+                AuthResult::Unknown(code) => add_message(
+                    ctx.clone(),
+                    &format!("message was not sent: server returned unknown auth code {code}"),
+                )?,
+            }
         } else {
-            send_message(connect_rac!(ctx), &message)?;
+            ctx.with_connection(|o| send_message(o, &message))?;
         }
     }
 
@@ -271,77 +348,20 @@ pub fn grab_avatar(message: &str) -> Option<String> {
     }
 }
 
-/// message -> (date, ip, text, (name, color), avatar)
+/// Parse message line
+///
+/// dialect - server dialect, that defines the date format, ip and auth marks
+/// timezone - server timezone, None is the local one
+/// user_agents - clients to recognize
+/// auth - server is known to have auth, otherwise auth status is unknown (read [`ChatMessage::parse`])
+///
+/// returns None if the line is empty or has no date
 pub fn parse_message(
     message: String,
-) -> Option<(
-    String,
-    Option<String>,
-    String,
-    Option<(String, String)>,
-    Option<String>,
-)> {
-    if message.is_empty() {
-        return None;
-    }
-
-    let (message, avatar) = if let Some(message) = AVATAR_REGEX.captures(&message) {
-        (
-            message.get(1)?.as_str().to_string(),
-            Some(message.get(2)?.as_str().to_string()),
-        )
-    } else {
-        (message, None)
-    };
-
-    let message = sanitize_message(message)?;
-
-    let date = DATE_REGEX.captures(&message)?;
-    let (date, message) = (
-        date.get(1)?.as_str().to_string(),
-        date.get(2)?.as_str().to_string(),
-    );
-
-    let message = message
-        .trim_start_matches("(UNREGISTERED)")
-        .trim_start_matches("(UNAUTHORIZED)")
-        .trim_start_matches("(UNAUTHENTICATED)")
-        .trim()
-        .to_string();
-
-    let (ip, message) = if let Some(message) = IP_REGEX.captures(&message) {
-        (
-            Some(message.get(1)?.as_str().to_string()),
-            message.get(2)?.as_str().to_string(),
-        )
-    } else {
-        (None, message)
-    };
-
-    let (message, nick) = if let Some((nick, message, color)) = DEFAULT_USER_AGENT
-        .captures(&message)
-        .and_then(|o| parse_user_agent(&o[2].to_string()))
-    {
-        (message, Some((nick, color)))
-    } else if let Some((nick, message, color)) = parse_user_agent(&message) {
-        (message, Some((nick, color)))
-    } else {
-        (message, None)
-    };
-
-    Some((date, ip, message, nick, avatar))
-}
-
-// message -> (nick, content, color)
-pub fn parse_user_agent(message: &str) -> Option<(String, String, String)> {
-    for (re, color) in USER_AGENTS.iter() {
-        if let Some(captures) = re.captures(message) {
-            return Some((
-                captures[1].to_string(),
-                captures[2].to_string(),
-                color.clone(),
-            ));
-        }
-    }
-    None
+    dialect: Dialect,
+    timezone: Option<FixedOffset>,
+    user_agents: &UserAgents,
+    auth: bool,
+) -> Option<ChatMessage> {
+    ChatMessage::parse(&message, dialect, timezone, user_agents, auth)
 }
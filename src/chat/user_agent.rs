@@ -0,0 +1,146 @@
+use regex::{escape, Regex, RegexSet};
+
+/// Client that can be recognized by the message text
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent {
+    /// Client name
+    pub name: String,
+    /// Magic key before the name (like `리㹰`), empty for none
+    #[serde(default)]
+    pub marker: String,
+    /// Regex with the name and text groups, None is `{marker}<(.*?)> (.*)`
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// Name color in the chat
+    pub color: String,
+    /// Icon name from the icon theme
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+impl UserAgent {
+    pub fn new(name: &str, marker: &str, color: &str) -> UserAgent {
+        UserAgent {
+            name: name.to_string(),
+            marker: marker.to_string(),
+            regex: None,
+            color: color.to_string(),
+            icon: None,
+        }
+    }
+
+    /// Regex of the message text
+    pub fn pattern(&self) -> String {
+        self.regex
+            .clone()
+            .unwrap_or_else(|| format!("{}<(.*?)> (.*)", escape(&self.marker)))
+    }
+}
+
+/// Clients that bRAC knows without config, the last one matches any `<name> text`
+pub fn built_in_user_agents() -> Vec<UserAgent> {
+    vec![
+        UserAgent::new("bRAC", "\u{B9AC}\u{3E70}", "#70fa7a"),
+        UserAgent::new("CRAB", "\u{2550}\u{2550}\u{2550}", "#fa7070"),
+        UserAgent::new("Mefidroniy", "\u{00B0}\u{0298}", "#da70fa"),
+        UserAgent::new("cRACk", "\u{2042}", "#f8b91b"),
+        UserAgent::new("Snowdrop", "\u{0D9E}", "#aeff00"),
+        UserAgent::new("Crack", "\u{30C4}", "#ff5733"),
+        UserAgent::new("clRAC", "", "#70fadc"),
+    ]
+}
+
+/// Registry of the recognized clients
+///
+/// All regexes are matched at once, the first matching client wins
+#[derive(Debug, Clone)]
+pub struct UserAgents {
+    agents: Vec<UserAgent>,
+    regexes: Vec<Regex>,
+    set: RegexSet,
+}
+
+impl UserAgents {
+    /// Create registry
+    ///
+    /// agents - clients in the matching order
+    ///
+    /// returns error if some regex is invalid or has less than two groups
+    pub fn new(agents: Vec<UserAgent>) -> Result<UserAgents, regex::Error> {
+        let regexes = agents
+            .iter()
+            .map(|o| {
+                let regex = Regex::new(&o.pattern())?;
+                if regex.captures_len() < 3 {
+                    return Err(regex::Error::Syntax(format!(
+                        "{}: regex must have name and text groups",
+                        o.name
+                    )));
+                }
+                Ok(regex)
+            })
+            .collect::<Result<Vec<Regex>, regex::Error>>()?;
+
+        Ok(UserAgents {
+            set: RegexSet::new(regexes.iter().map(|o| o.as_str()))?,
+            agents,
+            regexes,
+        })
+    }
+
+    /// Registry of the built-in clients
+    pub fn built_in() -> UserAgents {
+        UserAgents::new(built_in_user_agents()).expect("built-in user agents are valid")
+    }
+
+    /// Registry of the custom clients and then the built-in ones
+    ///
+    /// custom - clients from config, they replace built-in clients with the same name,
+    /// invalid ones are skipped
+    pub fn with_custom(custom: &[UserAgent]) -> UserAgents {
+        let mut agents = Vec::new();
+
+        for agent in custom {
+            match UserAgents::new(vec![agent.clone()]) {
+                Ok(_) => agents.push(agent.clone()),
+                Err(e) => println!("user agent {} skipped: {e}", agent.name),
+            }
+        }
+
+        for agent in built_in_user_agents() {
+            if !agents.iter().any(|o| o.name == agent.name) {
+                agents.push(agent);
+            }
+        }
+
+        UserAgents::new(agents).expect("user agents were checked")
+    }
+
+    /// Clients in the matching order
+    pub fn agents(&self) -> &[UserAgent] {
+        &self.agents
+    }
+
+    /// Find client by name
+    pub fn get(&self, name: &str) -> Option<&UserAgent> {
+        self.agents.iter().find(|o| o.name == name)
+    }
+
+    /// message -> (nick, content, client)
+    pub fn parse(&self, message: &str) -> Option<(String, String, &UserAgent)> {
+        let index = self.set.matches(message).iter().next()?;
+        let captures = self.regexes[index].captures(message)?;
+
+        Some((
+            captures.get(1)?.as_str().to_string(),
+            captures.get(2)?.as_str().to_string(),
+            &self.agents[index],
+        ))
+    }
+}
+
+impl Default for UserAgents {
+    fn default() -> UserAgents {
+        UserAgents::built_in()
+    }
+}
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
 use bRAC::chat::{
-    config::{get_config_path, load_config, Args},
+    config::{get_config_path, get_known_hosts_path, load_config, Args},
     ctx::Context,
 };
-use bRAC::proto::{connect, read_messages, send_message};
+use bRAC::proto::{
+    connect, read_messages, send_message, tls::KnownHosts, Recorder, TlsVerification,
+};
 use clap::Parser;
 
 fn main() {
@@ -26,24 +28,41 @@ fn main() {
 
     args.patch_config(&mut config);
 
+    let tls = TlsVerification::new(
+        config.tls_mode,
+        Arc::new(KnownHosts::load(get_known_hosts_path())),
+    );
+
+    let mut options = config.connect_options();
+    if config.record_enabled {
+        options.recorder =
+            Some(Recorder::open(config.capture_path()).expect("Error opening capture"));
+    }
+
     if args.read_messages {
-        let mut stream =
-            connect(&config.host, config.proxy.clone()).expect("Error reading message");
+        let mut stream = connect(&config.host, config.server_proxy(), &tls, &options)
+            .expect("Error reading message");
 
         print!(
             "{}",
-            read_messages(&mut stream, config.max_messages, 0, false)
-                .ok()
-                .flatten()
-                .expect("Error reading messages")
-                .0
-                .join("\n")
+            read_messages(
+                &mut stream,
+                config.max_messages,
+                0,
+                false,
+                config.dialect().unwrap_or_default()
+            )
+            .ok()
+            .flatten()
+            .expect("Error reading messages")
+            .0
+            .join("\n")
         );
     }
 
     if let Some(message) = &args.send_message {
-        let mut stream =
-            connect(&config.host, config.proxy.clone()).expect("Error sending message");
+        let mut stream = connect(&config.host, config.server_proxy(), &tls, &options)
+            .expect("Error sending message");
 
         send_message(&mut stream, message).expect("Error sending message");
     }
@@ -0,0 +1,413 @@
+use std::{fmt::Debug, io};
+
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{lookup_host, TcpStream},
+    time::timeout,
+};
+use tokio_io_timeout::TimeoutStream;
+use tokio_tungstenite::{client_async_with_config, tungstenite::protocol::Role, WebSocketStream};
+
+use super::{
+    history_page, page_offset,
+    proxy::{
+        check_connect_response, check_socks5_password, connect_request, parse_chain, proxy_error,
+        socks5_greeting, socks5_method, socks5_password, socks5_reply_rest, ProxyScheme,
+        SocksTarget, MAX_RESPONSE_HEAD,
+    },
+    record::{RecordingStream, Replay},
+    tls, AuthResult, ConnectOptions, Dialect, Error, ProxyUrl, RacUrl, RegisterResult,
+    TlsVerification,
+};
+
+pub mod rac;
+pub mod wrac;
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug> Stream for T {}
+
+pub enum RacStream {
+    /// WebSocket stream is much bigger than the raw one, so it is boxed
+    WRAC(Box<WebSocketStream<Box<dyn Stream>>>),
+    RAC(Box<dyn Stream>),
+}
+
+/// Create RAC connection (async version of [`super::connect`])
+///
+/// host - host string, example: "example.com:12345", "example.com" (default port is 42666),
+/// "rac+unix:///run/rac.sock"
+/// proxy - proxy URL or comma separated proxy chain, not used for Unix sockets
+/// tls - certificate verification for racs:// and wracs://
+/// options - timeouts and limits (connect timeout is used for the whole connection process)
+pub async fn connect(
+    host: &str,
+    proxy: Option<String>,
+    tls: &TlsVerification,
+    options: &ConnectOptions,
+) -> Result<RacStream, Error> {
+    timeout(
+        options.connect_timeout,
+        connect_inner(host, proxy, tls, options),
+    )
+    .await
+    .map_err(|_| Error::Io(io::ErrorKind::TimedOut.into()))?
+}
+
+async fn connect_inner(
+    host: &str,
+    proxy: Option<String>,
+    tls: &TlsVerification,
+    options: &ConnectOptions,
+) -> Result<RacStream, Error> {
+    let url = RacUrl::parse(host)?;
+    let host = url.address();
+    let chain = parse_chain(&proxy.unwrap_or_default())?;
+
+    let stream: Box<dyn Stream> = if url.is_unix() {
+        connect_unix(&host).await?
+    } else if !chain.is_empty() {
+        connect_proxy(&chain, &host).await?
+    } else {
+        Box::new(TcpStream::connect(&host).await.map_err(Error::Connect)?)
+    };
+
+    let stream: Box<dyn Stream> = if url.is_ssl() {
+        let stream = tokio_native_tls::TlsConnector::from(tls::build_connector(tls)?)
+            .connect(&url.host.name(), stream)
+            .await?;
+        tls::check_pin(tls, &host, stream.get_ref().peer_certificate()?)?;
+
+        Box::new(stream)
+    } else {
+        stream
+    };
+
+    // recorded after TLS, so the capture has the protocol data
+    let stream: Box<dyn Stream> = match &options.recorder {
+        Some(recorder) => Box::new(RecordingStream::new(
+            stream,
+            recorder.clone(),
+            &url.to_string(),
+            url.is_wrac(),
+        )),
+        None => stream,
+    };
+
+    let mut stream = TimeoutStream::new(stream);
+    stream.set_read_timeout(Some(options.read_timeout));
+    stream.set_write_timeout(Some(options.write_timeout));
+    let stream: Box<dyn Stream> = Box::new(Box::pin(stream));
+
+    if url.is_wrac() {
+        let (client, _) = client_async_with_config(
+            url.websocket_request(&options.headers)?,
+            stream,
+            Some(options.websocket_config()),
+        )
+        .await?;
+        Ok(RacStream::WRAC(Box::new(client)))
+    } else {
+        Ok(RacStream::RAC(stream))
+    }
+}
+
+/// Connect to the target through the proxy chain (async version of [`super::proxy::connect`])
+async fn connect_proxy(chain: &[ProxyUrl], target: &str) -> Result<Box<dyn Stream>, Error> {
+    let first = chain
+        .first()
+        .ok_or(Error::ProxyParse("empty proxy chain".to_string()))?;
+
+    let mut stream: Box<dyn Stream> = Box::new(
+        TcpStream::connect(first.address())
+            .await
+            .map_err(Error::Proxy)?,
+    );
+
+    for (i, proxy) in chain.iter().enumerate() {
+        let next = chain
+            .get(i + 1)
+            .map(|o| o.address())
+            .unwrap_or(target.to_string());
+
+        stream = proxy_handshake(stream, proxy, &next).await?;
+    }
+
+    Ok(stream)
+}
+
+/// Open the tunnel to the target through the proxy that the stream is connected to
+async fn proxy_handshake(
+    mut stream: Box<dyn Stream>,
+    proxy: &ProxyUrl,
+    target: &str,
+) -> Result<Box<dyn Stream>, Error> {
+    match proxy.scheme {
+        ProxyScheme::Socks5 | ProxyScheme::Socks5h => {
+            let target = match SocksTarget::parse(target)? {
+                SocksTarget::Domain(..) if proxy.scheme == ProxyScheme::Socks5 => SocksTarget::Ip(
+                    lookup_host(target)
+                        .await
+                        .map_err(Error::Resolve)?
+                        .next()
+                        .ok_or(Error::Resolve(io::ErrorKind::NotFound.into()))?,
+                ),
+                target => target,
+            };
+
+            socks5_handshake(&mut stream, &proxy.auth, &target)
+                .await
+                .map_err(Error::Proxy)?;
+
+            Ok(stream)
+        }
+        ProxyScheme::Http | ProxyScheme::Https => {
+            let mut stream: Box<dyn Stream> = if proxy.scheme == ProxyScheme::Https {
+                Box::new(
+                    tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?)
+                        .connect(&proxy.host.name(), stream)
+                        .await?,
+                )
+            } else {
+                stream
+            };
+
+            http_handshake(&mut stream, &proxy.auth, target)
+                .await
+                .map_err(Error::Proxy)?;
+
+            Ok(stream)
+        }
+    }
+}
+
+async fn socks5_handshake(
+    stream: &mut Box<dyn Stream>,
+    auth: &Option<(String, String)>,
+    target: &SocksTarget,
+) -> io::Result<()> {
+    stream.write_all(&socks5_greeting(auth)).await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+
+    if let Some((user, pass)) = socks5_method(reply, auth)? {
+        stream.write_all(&socks5_password(user, pass)?).await?;
+        stream.read_exact(&mut reply).await?;
+        check_socks5_password(reply)?;
+    }
+
+    stream.write_all(&target.connect_request()).await?;
+
+    let mut head = [0; 5];
+    stream.read_exact(&mut head).await?;
+    // bound address is not needed, but it is sent before the data
+    let mut bound = vec![0; socks5_reply_rest(head)?];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+async fn http_handshake(
+    stream: &mut Box<dyn Stream>,
+    auth: &Option<(String, String)>,
+    target: &str,
+) -> io::Result<()> {
+    stream
+        .write_all(connect_request(target, auth).as_bytes())
+        .await?;
+    stream.flush().await?;
+
+    // read by byte, so nothing after the head is taken from the stream
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD || stream.read(&mut byte).await? == 0 {
+            return Err(proxy_error("connection closed before the response"));
+        }
+        head.push(byte[0]);
+    }
+
+    check_connect_response(&head)
+}
+
+/// Open the next recorded connection (async version of [`super::record::Replay::connect`])
+///
+/// options - WebSocket limits are used for WRAC
+pub async fn connect_replay(
+    replay: &mut Replay,
+    options: &ConnectOptions,
+) -> Result<RacStream, Error> {
+    let (url, stream) = replay.next_stream()?;
+    let stream: Box<dyn Stream> = Box::new(stream);
+
+    if url.is_wrac() {
+        Ok(RacStream::WRAC(Box::new(
+            WebSocketStream::from_raw_socket(
+                stream,
+                Role::Client,
+                Some(options.websocket_config()),
+            )
+            .await,
+        )))
+    } else {
+        Ok(RacStream::RAC(stream))
+    }
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: &str) -> Result<Box<dyn Stream>, Error> {
+    Ok(Box::new(
+        UnixStream::connect(path).await.map_err(Error::Connect)?,
+    ))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_: &str) -> Result<Box<dyn Stream>, Error> {
+    Err(Error::Connect(io::ErrorKind::Unsupported.into()))
+}
+
+/// Send message
+///
+/// stream - any stream that can be written to
+/// message - message text
+pub async fn send_message(stream: &mut RacStream, message: &str) -> Result<(), Error> {
+    match stream {
+        RacStream::WRAC(websocket) => wrac::send_message(websocket, message).await,
+        RacStream::RAC(stream) => rac::send_message(stream, message).await,
+    }
+}
+
+/// Send keepalive ping (only WRAC has pings, RAC connection is closed after every request)
+///
+/// stream - any stream that can be written to
+pub async fn send_ping(stream: &mut RacStream) -> Result<(), Error> {
+    match stream {
+        RacStream::WRAC(websocket) => wrac::send_ping(websocket).await,
+        RacStream::RAC(_) => Ok(()),
+    }
+}
+
+/// Register user
+///
+/// stream - any stream that can be written to
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns registration result
+pub async fn register_user(
+    stream: &mut RacStream,
+    name: &str,
+    password: &str,
+    dialect: Dialect,
+) -> Result<RegisterResult, Error> {
+    match stream {
+        RacStream::WRAC(websocket) => wrac::register_user(websocket, name, password, dialect).await,
+        RacStream::RAC(stream) => rac::register_user(stream, name, password, dialect).await,
+    }
+}
+
+/// Send message with auth
+///
+/// stream - any stream that can be written to
+/// message - message text
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns auth result (message is sent only if it is [`AuthResult::Sent`])
+pub async fn send_message_auth(
+    stream: &mut RacStream,
+    name: &str,
+    password: &str,
+    message: &str,
+    dialect: Dialect,
+) -> Result<AuthResult, Error> {
+    match stream {
+        RacStream::WRAC(websocket) => {
+            wrac::send_message_auth(websocket, name, password, message, dialect).await
+        }
+        RacStream::RAC(stream) => {
+            rac::send_message_auth(stream, name, password, message, dialect).await
+        }
+    }
+}
+
+/// Read messages
+///
+/// max_messages - max messages in list
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (messages, packet size)
+pub async fn read_messages(
+    stream: &mut RacStream,
+    max_messages: usize,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<String>, usize)>, Error> {
+    match stream {
+        RacStream::WRAC(websocket) => {
+            wrac::read_messages(websocket, max_messages, last_size, chunked, dialect).await
+        }
+        RacStream::RAC(stream) => {
+            rac::read_messages(stream, max_messages, last_size, chunked, dialect).await
+        }
+    }
+}
+
+/// Read messages as the server sent them
+///
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (data, packet size), data is the whole history if it is not a chunk
+pub async fn read_messages_data(
+    stream: &mut RacStream,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    match stream {
+        RacStream::WRAC(websocket) => {
+            wrac::read_messages_data(websocket, last_size, chunked, dialect).await
+        }
+        RacStream::RAC(stream) => {
+            rac::read_messages_data(stream, last_size, chunked, dialect).await
+        }
+    }
+}
+
+/// Read page of the older history (messages before `end`)
+///
+/// end - offset of the first already read message
+/// page_size - max page size in bytes
+/// dialect - server dialect
+///
+/// returns (messages, offset of the first message in the page), offset is zero at the start of the history \
+/// returns no messages and `end` as the offset if the first message is bigger than the page
+pub async fn read_history_page(
+    stream: &mut RacStream,
+    end: usize,
+    page_size: usize,
+    dialect: Dialect,
+) -> Result<(Vec<String>, usize), Error> {
+    if end == 0 {
+        return Ok((Vec::new(), 0));
+    }
+
+    let offset = page_offset(end, page_size);
+
+    let (data, packet_size) = match stream {
+        RacStream::WRAC(websocket) => wrac::read_history(websocket, offset, dialect).await?,
+        RacStream::RAC(stream) => rac::read_history(stream, offset, dialect).await?,
+    };
+
+    history_page(data, offset, end, packet_size, dialect)
+}
@@ -0,0 +1,206 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::proto::{chunk_data, chunk_offset, AuthResult, Dialect, Error, RegisterResult};
+
+/// Send message
+///
+/// stream - any stream that can be written to
+/// message - message text
+pub async fn send_message(
+    stream: &mut (impl AsyncWrite + Unpin),
+    message: &str,
+) -> Result<(), Error> {
+    stream
+        .write_all(format!("\x01{message}").as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Register user
+///
+/// stream - any stream that can be written to
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns registration result
+pub async fn register_user(
+    stream: &mut (impl AsyncWrite + AsyncRead + Unpin),
+    name: &str,
+    password: &str,
+    dialect: Dialect,
+) -> Result<RegisterResult, Error> {
+    dialect.check_auth()?;
+    stream
+        .write_all(format!("\x03{name}\n{password}").as_bytes())
+        .await?;
+    let reply = read_reply(stream).await?;
+    Ok(RegisterResult::from(reply.unwrap_or(0)))
+}
+
+/// Send message with auth
+///
+/// stream - any stream that can be written to
+/// message - message text
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns auth result (message is sent only if it is [`AuthResult::Sent`])
+pub async fn send_message_auth(
+    stream: &mut (impl AsyncWrite + AsyncRead + Unpin),
+    name: &str,
+    password: &str,
+    message: &str,
+    dialect: Dialect,
+) -> Result<AuthResult, Error> {
+    dialect.check_auth()?;
+    stream
+        .write_all(format!("\x02{name}\n{password}\n{message}").as_bytes())
+        .await?;
+    let reply = read_reply(stream).await?;
+    Ok(AuthResult::from(reply.unwrap_or(0)))
+}
+
+/// Read reply code to the auth or registration packet (async version of the RAC one)
+///
+/// Server replies only on errors and closes the connection after the packet
+///
+/// returns None if the connection was closed without a reply
+async fn read_reply(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<u8>, Error> {
+    match skip_null(stream).await {
+        Ok(reply) => Ok(Some(reply[0])),
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Skip null bytes and return first non-null byte
+pub async fn skip_null(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, Error> {
+    loop {
+        let mut buf = vec![0; 1];
+        stream.read_exact(&mut buf).await?;
+        if buf[0] != 0 {
+            break Ok(buf);
+        }
+    }
+}
+
+/// Read messages
+///
+/// max_messages - max messages in list
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (messages, packet size)
+pub async fn read_messages(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    max_messages: usize,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<String>, usize)>, Error> {
+    Ok(read_messages_data(stream, last_size, chunked, dialect)
+        .await?
+        .map(|(data, packet_size)| (dialect.parse_messages(&data, max_messages), packet_size)))
+}
+
+/// Read messages as the server sent them
+///
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (data, packet size), data is the whole history if it is not a chunk
+pub async fn read_messages_data(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    stream.write_all(&[0x00]).await?;
+
+    let packet_size = {
+        let mut data = skip_null(stream).await?;
+        let mut buf = vec![0; 10];
+        let len = stream.read(&mut buf).await?;
+        buf.truncate(len);
+        data.append(&mut buf);
+
+        dialect.parse_size(&data)?
+    };
+
+    if last_size == packet_size {
+        return Ok(None);
+    }
+
+    let chunked = chunked && last_size != 0;
+
+    let to_read = if !chunked {
+        stream.write_all(&[0x01]).await?;
+        packet_size
+    } else {
+        let offset = chunk_offset(last_size, packet_size)?;
+        stream
+            .write_all(format!("\x02{}", offset).as_bytes())
+            .await?;
+        packet_size - offset
+    };
+
+    let mut packet_data = skip_null(stream).await?;
+    let mut buf = vec![0; to_read.saturating_sub(packet_data.len())];
+    stream.read_exact(&mut buf).await?;
+    packet_data.append(&mut buf);
+
+    if chunked {
+        packet_data = chunk_data(packet_data, last_size, packet_size)?;
+    }
+
+    Ok(Some((packet_data, packet_size)))
+}
+
+/// Read raw history bytes starting from the offset
+///
+/// offset - offset in bytes (zero is the whole history)
+/// dialect - server dialect
+///
+/// returns (data, packet size)
+pub async fn read_history(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    offset: usize,
+    dialect: Dialect,
+) -> Result<(Vec<u8>, usize), Error> {
+    stream.write_all(&[0x00]).await?;
+
+    let packet_size = {
+        let mut data = skip_null(stream).await?;
+        let mut buf = vec![0; 10];
+        let len = stream.read(&mut buf).await?;
+        buf.truncate(len);
+        data.append(&mut buf);
+
+        dialect.parse_size(&data)?
+    };
+
+    if offset >= packet_size {
+        return Ok((Vec::new(), packet_size));
+    }
+
+    if offset == 0 {
+        stream.write_all(&[0x01]).await?;
+    } else {
+        stream
+            .write_all(format!("\x02{}", offset).as_bytes())
+            .await?;
+    }
+
+    let mut data = skip_null(stream).await?;
+    let mut buf = vec![0; (packet_size - offset).saturating_sub(data.len())];
+    stream.read_exact(&mut buf).await?;
+    data.append(&mut buf);
+
+    Ok((data, packet_size))
+}
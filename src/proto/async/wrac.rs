@@ -0,0 +1,272 @@
+use std::io;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+use crate::proto::{
+    chunk_data, chunk_offset, wrac::is_closed, AuthResult, Dialect, Error, RegisterResult,
+};
+
+/// Read next data message, control frames are skipped (pings are answered with pongs),
+/// closed connection is an error
+async fn read_message(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+) -> Result<Message, Error> {
+    loop {
+        match stream
+            .next()
+            .await
+            .ok_or(Error::Io(io::ErrorKind::UnexpectedEof.into()))??
+        {
+            // pong is queued by the read and sent by the flush
+            Message::Ping(_) => stream.flush().await?,
+            Message::Pong(_) | Message::Frame(_) => {}
+            Message::Close(_) => {
+                return Err(Error::WebSocket(Box::new(
+                    tokio_tungstenite::tungstenite::Error::ConnectionClosed,
+                )))
+            }
+            msg => return Ok(msg),
+        }
+    }
+}
+
+/// Read next binary message
+async fn read_binary(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+) -> Result<Vec<u8>, Error> {
+    let msg = read_message(stream).await?;
+    if !msg.is_binary() {
+        return Err(Error::Protocol("msg is not binary".to_string()));
+    }
+    Ok(msg.into_data().to_vec())
+}
+
+/// Send ping to keep the connection alive, pong is skipped by the next read
+pub async fn send_ping(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+) -> Result<(), Error> {
+    stream.send(Message::Ping(Default::default())).await?;
+    Ok(())
+}
+
+/// Get response code from the server reply (empty or non-binary reply is 0)
+fn response_code(msg: Message) -> u8 {
+    if msg.is_binary() {
+        *msg.into_data().first().unwrap_or(&0)
+    } else {
+        0
+    }
+}
+
+/// Send auth or registration packet and read the reply code (async version of the WRAC one)
+///
+/// Server replies only on errors and keeps the connection open, so the size request
+/// is sent after the packet: if the first reply is the size, there was no reply to the packet
+/// (reply codes are not digits)
+///
+/// returns None if there was no reply or the server closed the connection without it
+async fn request_reply(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    packet: String,
+    dialect: Dialect,
+) -> Result<Option<u8>, Error> {
+    stream
+        .feed(Message::Binary(packet.into_bytes().into()))
+        .await?;
+    stream.send(Message::Binary(vec![0x00].into())).await?;
+
+    let msg = match read_message(stream).await {
+        Ok(msg) => msg,
+        Err(e) if is_closed(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if let Message::Binary(data) = &msg {
+        if dialect.parse_size(data).is_ok() {
+            return Ok(None);
+        }
+    }
+
+    // skip the size reply
+    match read_message(stream).await {
+        Ok(_) => {}
+        Err(e) if is_closed(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(Some(response_code(msg)))
+}
+
+/// Send message
+///
+/// stream - any stream that can be written to
+/// message - message text
+pub async fn send_message(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    message: &str,
+) -> Result<(), Error> {
+    stream
+        .send(Message::Binary(
+            format!("\x01{message}").as_bytes().to_vec().into(),
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Register user
+///
+/// stream - any stream that can be written to
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns registration result
+pub async fn register_user(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    name: &str,
+    password: &str,
+    dialect: Dialect,
+) -> Result<RegisterResult, Error> {
+    dialect.check_auth()?;
+    let reply = request_reply(stream, format!("\x03{name}\n{password}"), dialect).await?;
+    Ok(RegisterResult::from(reply.unwrap_or(0)))
+}
+
+/// Send message with auth
+///
+/// stream - any stream that can be written to
+/// message - message text
+/// name - user name
+/// password - user password
+/// dialect - server dialect (error if it has no auth)
+///
+/// returns auth result (message is sent only if it is [`AuthResult::Sent`])
+pub async fn send_message_auth(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    name: &str,
+    password: &str,
+    message: &str,
+    dialect: Dialect,
+) -> Result<AuthResult, Error> {
+    dialect.check_auth()?;
+    let reply = request_reply(
+        stream,
+        format!("\x02{name}\n{password}\n{message}"),
+        dialect,
+    )
+    .await?;
+    Ok(AuthResult::from(reply.unwrap_or(0)))
+}
+
+/// Read messages
+///
+/// max_messages - max messages in list
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (messages, packet size)
+pub async fn read_messages(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    max_messages: usize,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<String>, usize)>, Error> {
+    Ok(read_messages_data(stream, last_size, chunked, dialect)
+        .await?
+        .map(|(data, packet_size)| (dialect.parse_messages(&data, max_messages), packet_size)))
+}
+
+/// Read messages as the server sent them
+///
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (data, packet size), data is the whole history if it is not a chunk
+pub async fn read_messages_data(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    stream.send(Message::Binary(vec![0x00].into())).await?;
+
+    let packet_size = dialect.parse_size(&read_binary(stream).await?)?;
+
+    if last_size == packet_size {
+        return Ok(None);
+    }
+
+    let chunked = chunked && last_size != 0;
+
+    let to_read = if !chunked {
+        stream
+            .send(Message::Binary(vec![0x00, 0x01].into()))
+            .await?;
+        packet_size
+    } else {
+        let offset = chunk_offset(last_size, packet_size)?;
+        stream
+            .send(Message::Binary(
+                format!("\x00\x02{}", offset).as_bytes().to_vec().into(),
+            ))
+            .await?;
+        packet_size - offset
+    };
+
+    let mut packet_data = read_binary(stream).await?;
+
+    if packet_data.len() > to_read {
+        return Err(Error::Protocol("too big msg".to_string()));
+    }
+
+    if chunked {
+        packet_data = chunk_data(packet_data, last_size, packet_size)?;
+    }
+
+    Ok(Some((packet_data, packet_size)))
+}
+
+/// Read raw history bytes starting from the offset
+///
+/// offset - offset in bytes (zero is the whole history)
+/// dialect - server dialect
+///
+/// returns (data, packet size)
+pub async fn read_history(
+    stream: &mut WebSocketStream<impl AsyncWrite + AsyncRead + Unpin>,
+    offset: usize,
+    dialect: Dialect,
+) -> Result<(Vec<u8>, usize), Error> {
+    stream.send(Message::Binary(vec![0x00].into())).await?;
+
+    let packet_size = dialect.parse_size(&read_binary(stream).await?)?;
+
+    if offset >= packet_size {
+        return Ok((Vec::new(), packet_size));
+    }
+
+    if offset == 0 {
+        stream
+            .send(Message::Binary(vec![0x00, 0x01].into()))
+            .await?;
+    } else {
+        stream
+            .send(Message::Binary(
+                format!("\x00\x02{}", offset).as_bytes().to_vec().into(),
+            ))
+            .await?;
+    }
+
+    let data = read_binary(stream).await?;
+
+    if data.len() > packet_size - offset {
+        return Err(Error::Protocol("too big msg".to_string()));
+    }
+
+    Ok((data, packet_size))
+}
@@ -0,0 +1,233 @@
+use chrono::NaiveDateTime;
+
+use super::Error;
+
+/// Server flavour of the RAC protocol
+///
+/// Defines how size replies and message lines are parsed and whether auth packets are supported,
+/// a new server flavour is a new variant here
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+)]
+pub enum Dialect {
+    /// RACv1.0 (AlmatyD): no auth, no ip in messages, lines may end with `\r\n`
+    #[serde(rename = "rac1.0")]
+    #[value(name = "rac1.0")]
+    Rac1_0,
+    /// RACv1.99 (old sRAC): no auth, ip in braces
+    #[serde(rename = "rac1.99")]
+    #[value(name = "rac1.99")]
+    Rac1_99,
+    /// RACv2.0 (sRAC, bRAC server): auth, unauthenticated messages marked with `(UNREGISTERED)`
+    #[default]
+    #[serde(rename = "rac2")]
+    #[value(name = "rac2")]
+    Rac2,
+    /// RACv2.0 as CRAB speaks it: unauthenticated messages marked with `(UNAUTHENTICATED)`
+    #[serde(rename = "crab")]
+    #[value(name = "crab")]
+    Crab,
+}
+
+/// Message line split by the dialect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageLine {
+    /// Date as the server sent it
+    pub date: String,
+    /// Sender ip (if the dialect has it and server shows it)
+    pub ip: Option<String>,
+    /// Message was marked as unauthenticated by the server
+    pub unauthenticated: bool,
+    /// Message text (with the user agent and name)
+    pub text: String,
+}
+
+impl Dialect {
+    pub const ALL: [Dialect; 4] = [
+        Dialect::Rac1_0,
+        Dialect::Rac1_99,
+        Dialect::Rac2,
+        Dialect::Crab,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Rac1_0 => "RACv1.0 (AlmatyD)",
+            Dialect::Rac1_99 => "RACv1.99",
+            Dialect::Rac2 => "RACv2.0 (sRAC)",
+            Dialect::Crab => "RACv2.0 (CRAB)",
+        }
+    }
+
+    /// Are 0x02 (message with auth) and 0x03 (registration) packets supported
+    pub fn supports_auth(&self) -> bool {
+        matches!(self, Dialect::Rac2 | Dialect::Crab)
+    }
+
+    /// Are sender ips sent in braces after the date
+    pub fn has_ip(&self) -> bool {
+        !matches!(self, Dialect::Rac1_0)
+    }
+
+    /// Prefixes the server adds to messages sent without auth
+    pub fn unauthenticated_prefixes(&self) -> &'static [&'static str] {
+        match self {
+            Dialect::Rac1_0 | Dialect::Rac1_99 => &[],
+            Dialect::Rac2 => &["(UNREGISTERED)", "(UNAUTHORIZED)"],
+            Dialect::Crab => &["(UNAUTHENTICATED)"],
+        }
+    }
+
+    /// Date formats of message lines (chrono strftime), tried in order
+    pub fn date_formats(&self) -> &'static [&'static str] {
+        match self {
+            // old servers may write seconds too
+            Dialect::Rac1_0 | Dialect::Rac1_99 => &["%d.%m.%Y %H:%M", "%d.%m.%Y %H:%M:%S"],
+            Dialect::Rac2 | Dialect::Crab => &["%d.%m.%Y %H:%M"],
+        }
+    }
+
+    /// Parse date of the message line (in the server timezone)
+    ///
+    /// returns None if the date matches none of the dialect formats
+    pub fn parse_date(&self, date: &str) -> Option<NaiveDateTime> {
+        self.date_formats()
+            .iter()
+            .find_map(|o| NaiveDateTime::parse_from_str(date.trim(), o).ok())
+    }
+
+    /// Parse reply to the 0x00 packet (padding null bytes and whitespace are ignored)
+    pub fn parse_size(&self, data: &[u8]) -> Result<usize, Error> {
+        Ok(String::from_utf8(data.to_vec())?
+            .trim_matches(|o: char| o == '\0' || o.is_ascii_whitespace())
+            .parse()?)
+    }
+
+    /// Split messages packet into lines
+    ///
+    /// max_messages - max messages in list (last ones are kept)
+    pub fn parse_messages(&self, data: &[u8], max_messages: usize) -> Vec<String> {
+        let data = String::from_utf8_lossy(data);
+
+        let lines: Vec<&str> = data.split("\n").collect();
+
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(max_messages))
+            .map(|o| match self {
+                Dialect::Rac1_0 => o.trim_end_matches('\r').to_string(),
+                _ => o.to_string(),
+            })
+            .collect()
+    }
+
+    /// Offset of the first message that [`Dialect::parse_messages`] keeps
+    ///
+    /// max_messages - max messages in list (last ones are kept)
+    ///
+    /// returns offset in bytes in the data
+    pub fn messages_start(&self, data: &[u8], max_messages: usize) -> usize {
+        let newlines: Vec<usize> = data
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| **o == b'\n')
+            .map(|(i, _)| i)
+            .collect();
+
+        match (newlines.len() + 1).saturating_sub(max_messages) {
+            0 => 0,
+            skip => newlines[skip - 1] + 1,
+        }
+    }
+
+    /// Split message line into date, ip, auth mark and text
+    ///
+    /// returns None if there is no date
+    pub fn parse_line(&self, line: &str) -> Option<MessageLine> {
+        let (date, text) = line.strip_prefix("[")?.split_once("] ")?;
+
+        let (text, marked) = self.strip_mark(text.trim_start());
+
+        // the mark can be before or after the ip
+        let (ip, text, unauthenticated) = match text
+            .strip_prefix("{")
+            .and_then(|o| o.split_once("} "))
+            .filter(|_| self.has_ip())
+        {
+            Some((ip, text)) => {
+                let (text, marked_after) = self.strip_mark(text);
+                (Some(ip.to_string()), text, marked || marked_after)
+            }
+            None => (None, text, marked),
+        };
+
+        Some(MessageLine {
+            date: date.to_string(),
+            ip,
+            unauthenticated,
+            text: text.to_string(),
+        })
+    }
+
+    /// Remove unauthenticated mark from the start of the text
+    ///
+    /// returns (text, was there a mark)
+    fn strip_mark<'a>(&self, text: &'a str) -> (&'a str, bool) {
+        for prefix in self.unauthenticated_prefixes() {
+            if let Some(text) = text.strip_prefix(prefix) {
+                return (text.trim_start(), true);
+            }
+        }
+        (text, false)
+    }
+
+    /// Error if the dialect has no auth packets
+    pub fn check_auth(&self) -> Result<(), Error> {
+        if self.supports_auth() {
+            Ok(())
+        } else {
+            Err(Error::Protocol(format!("{} has no auth", self.name())))
+        }
+    }
+
+    /// Guess dialect by the message lines
+    ///
+    /// RACv2.0 is kept unless the lines have something only RACv1 servers send
+    /// (`\r\n` line endings or dates with seconds), servers may hide ips and marks
+    ///
+    /// returns None if there are no lines with date
+    pub fn detect(lines: &[String]) -> Option<Dialect> {
+        let parsed = |dialect: Dialect| -> Vec<MessageLine> {
+            lines.iter().filter_map(|o| dialect.parse_line(o)).collect()
+        };
+
+        let rac2 = parsed(Dialect::Rac2);
+
+        let rac1 = lines.iter().any(|o| o.ends_with('\r'))
+            || rac2.iter().any(|o| {
+                Dialect::Rac2.parse_date(&o.date).is_none()
+                    && Dialect::Rac1_0.parse_date(&o.date).is_some()
+            });
+
+        if rac2.is_empty() {
+            None
+        } else if parsed(Dialect::Crab).iter().any(|o| o.unauthenticated) {
+            Some(Dialect::Crab)
+        } else if rac2.iter().any(|o| o.unauthenticated) || !rac1 {
+            Some(Dialect::Rac2)
+        } else if rac2.iter().any(|o| o.ip.is_some()) {
+            Some(Dialect::Rac1_99)
+        } else {
+            Some(Dialect::Rac1_0)
+        }
+    }
+}
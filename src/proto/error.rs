@@ -0,0 +1,146 @@
+use std::{fmt, io, num::ParseIntError, string::FromUtf8Error};
+
+use native_tls::HandshakeError;
+
+use super::AuthResult;
+
+/// Protocol layer error
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid RAC URL
+    UrlParse(String),
+    /// Invalid proxy URL
+    ProxyParse(String),
+    /// Host name resolution failed
+    Resolve(io::Error),
+    /// TCP connection to the server failed
+    Connect(io::Error),
+    /// Connection through the proxy failed
+    Proxy(io::Error),
+    /// TLS error
+    Tls(native_tls::Error),
+    /// WebSocket error (WRAC)
+    WebSocket(Box<tungstenite::Error>),
+    /// IO error on the established connection
+    Io(io::Error),
+    /// Server certificate doesn't match the pinned fingerprint (TOFU)
+    PinMismatch {
+        host: String,
+        expected: String,
+        actual: String,
+    },
+    /// Pinned certificate fingerprint couldn't be saved (TOFU)
+    PinSave(io::Error),
+    /// Server sent something that doesn't match the protocol
+    Protocol(String),
+    /// Server refused the message with auth
+    Auth(AuthResult),
+    /// Server history was truncated or changed since the last chunked read
+    HistoryReset {
+        last_size: usize,
+        packet_size: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UrlParse(url) => write!(f, "url parse error: {url}"),
+            Error::ProxyParse(url) => write!(f, "proxy parse error: {url}"),
+            Error::Resolve(e) => write!(f, "resolve error: {e}"),
+            Error::Connect(e) => write!(f, "connect error: {e}"),
+            Error::Proxy(e) => write!(f, "proxy error: {e}"),
+            Error::Tls(e) => write!(f, "tls error: {e}"),
+            Error::WebSocket(e) => write!(f, "websocket error: {e}"),
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::PinMismatch {
+                host,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "certificate of {host} has changed (pinned {expected}, got {actual})"
+            ),
+            Error::PinSave(e) => write!(f, "pin save error: {e}"),
+            Error::Protocol(e) => write!(f, "protocol error: {e}"),
+            Error::Auth(AuthResult::UserNotFound) => write!(f, "auth error: user does not exist"),
+            Error::Auth(AuthResult::WrongPassword) => write!(f, "auth error: incorrect password"),
+            Error::Auth(result) => write!(f, "auth error: code {}", result.code()),
+            Error::HistoryReset {
+                last_size,
+                packet_size,
+            } => write!(
+                f,
+                "history reset: server has {packet_size} bytes, {last_size} bytes were read"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Resolve(e)
+            | Error::Connect(e)
+            | Error::Proxy(e)
+            | Error::Io(e)
+            | Error::PinSave(e) => Some(e),
+            Error::Tls(e) => Some(e),
+            Error::WebSocket(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(e: native_tls::Error) -> Self {
+        Error::Tls(e)
+    }
+}
+
+impl<S> From<HandshakeError<S>> for Error {
+    fn from(e: HandshakeError<S>) -> Self {
+        match e {
+            HandshakeError::Failure(e) => Error::Tls(e),
+            HandshakeError::WouldBlock(_) => Error::Io(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        match e {
+            tungstenite::Error::Io(e) => Error::Io(e),
+            e => Error::WebSocket(Box::new(e)),
+        }
+    }
+}
+
+impl<R: tungstenite::handshake::HandshakeRole> From<tungstenite::HandshakeError<R>> for Error {
+    fn from(e: tungstenite::HandshakeError<R>) -> Self {
+        match e {
+            tungstenite::HandshakeError::Failure(e) => e.into(),
+            tungstenite::HandshakeError::Interrupted(_) => {
+                Error::Io(io::ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use tungstenite::{accept, client, Message};
+
+use super::{Error, RacStream, Stream};
+
+/// Timeout of the scripted server reads, so broken tests fail instead of hanging
+const SERVER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One direction of the in-memory connection
+#[derive(Debug, Default)]
+struct Channel {
+    state: Mutex<ChannelState>,
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    /// Written chunks, every read returns bytes from one chunk only (like one TCP packet)
+    chunks: VecDeque<Vec<u8>>,
+    /// One of the ends is dropped
+    closed: bool,
+}
+
+impl Channel {
+    fn closed(chunks: impl IntoIterator<Item = Vec<u8>>) -> Channel {
+        Channel {
+            state: Mutex::new(ChannelState {
+                chunks: chunks.into_iter().filter(|o| !o.is_empty()).collect(),
+                closed: true,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// In-memory stream for testing without real sockets
+///
+/// Every `write` call is delivered to the other end as a separate chunk,
+/// and every `read` returns bytes of one chunk at most.
+/// When one end is dropped, the other one reads EOF and can't write anymore
+#[derive(Debug)]
+pub struct MockStream {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl MockStream {
+    /// Two connected ends of the in-memory connection
+    pub fn pair() -> (MockStream, MockStream) {
+        let first = Arc::new(Channel::default());
+        let second = Arc::new(Channel::default());
+
+        (
+            MockStream::new(first.clone(), second.clone()),
+            MockStream::new(second, first),
+        )
+    }
+
+    /// Stream that reads the chunks and then EOF
+    ///
+    /// Written data can be got with [`MockStream::written`]
+    pub fn with_input(chunks: impl IntoIterator<Item = impl Into<Vec<u8>>>) -> MockStream {
+        MockStream::new(
+            Arc::new(Channel::closed(chunks.into_iter().map(Into::into))),
+            Arc::new(Channel::default()),
+        )
+    }
+
+    fn new(incoming: Arc<Channel>, outgoing: Arc<Channel>) -> MockStream {
+        MockStream {
+            incoming,
+            outgoing,
+            read_timeout: Mutex::new(None),
+        }
+    }
+
+    /// Data that is written to this end and not read by the other one yet
+    pub fn written(&self) -> Vec<u8> {
+        self.outgoing
+            .state
+            .lock()
+            .unwrap()
+            .chunks
+            .iter()
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let started = Instant::now();
+        let mut state = self.incoming.state.lock().unwrap();
+
+        loop {
+            if let Some(chunk) = state.chunks.front_mut() {
+                let len = buf.len().min(chunk.len());
+                buf[..len].copy_from_slice(&chunk[..len]);
+                chunk.drain(..len);
+                if chunk.is_empty() {
+                    state.chunks.pop_front();
+                }
+                return Ok(len);
+            }
+
+            if state.closed {
+                return Ok(0);
+            }
+
+            state = match timeout {
+                Some(timeout) => {
+                    let left = timeout
+                        .checked_sub(started.elapsed())
+                        .ok_or(io::Error::from(io::ErrorKind::TimedOut))?;
+                    self.incoming.ready.wait_timeout(state, left).unwrap().0
+                }
+                None => self.incoming.ready.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if !buf.is_empty() {
+            state.chunks.push_back(buf.to_vec());
+            self.outgoing.ready.notify_all();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl Stream for MockStream {
+    fn set_read_timeout(&self, timeout: Duration) {
+        *self.read_timeout.lock().unwrap() = Some(timeout);
+    }
+    fn set_write_timeout(&self, _: Duration) {}
+}
+
+/// Fake server that answers the requests by the script
+///
+/// Every step is an expected request packet and the reply to it (empty reply sends nothing).
+/// Server closes the connection after the last step or on the first unexpected request
+///
+/// ```
+/// use bRAC::proto::{mock::ScriptedServer, read_messages, Dialect};
+///
+/// let (mut stream, server) = ScriptedServer::new()
+///     .expect(b"\x00", b"12")
+///     .expect(b"\x01", b"hello\nworld\n")
+///     .spawn(false)
+///     .unwrap();
+///
+/// let (messages, size) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2).unwrap().unwrap();
+///
+/// assert_eq!(messages, vec!["hello", "world", ""]);
+/// assert_eq!(size, 12);
+/// assert_eq!(server.join().unwrap(), Ok(()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedServer {
+    steps: Vec<(Vec<u8>, Vec<u8>)>,
+    pings: bool,
+    close: bool,
+}
+
+impl ScriptedServer {
+    pub fn new() -> ScriptedServer {
+        ScriptedServer::default()
+    }
+
+    /// Add step to the script
+    ///
+    /// request - expected packet from the client (WRAC message data for WRAC)
+    /// reply - data sent back, empty is nothing
+    pub fn expect(mut self, request: impl Into<Vec<u8>>, reply: impl Into<Vec<u8>>) -> Self {
+        self.steps.push((request.into(), reply.into()));
+        self
+    }
+
+    /// Send WebSocket ping before every reply (WRAC only), the script fails if pings are not answered
+    pub fn with_pings(mut self) -> Self {
+        self.pings = true;
+        self
+    }
+
+    /// Send WebSocket close frame after the last step (WRAC only)
+    pub fn close(mut self) -> Self {
+        self.close = true;
+        self
+    }
+
+    /// Run the script in a new thread
+    ///
+    /// wrac - use WRAC framing (WebSocket handshake is done before returning)
+    ///
+    /// returns client stream and server thread handle, that returns error if the script was not followed
+    pub fn spawn(self, wrac: bool) -> Result<(RacStream, JoinHandle<Result<(), String>>), Error> {
+        let (client_stream, server_stream) = MockStream::pair();
+        server_stream.set_read_timeout(SERVER_READ_TIMEOUT);
+
+        let handle = thread::spawn(move || {
+            if wrac {
+                self.run_wrac(server_stream)
+            } else {
+                self.run_rac(server_stream)
+            }
+        });
+
+        let client_stream: Box<dyn Stream> = Box::new(client_stream);
+
+        let stream = if wrac {
+            RacStream::WRAC(client("ws://localhost/", client_stream)?.0)
+        } else {
+            RacStream::RAC(client_stream)
+        };
+
+        Ok((stream, handle))
+    }
+
+    fn run_rac(self, mut stream: MockStream) -> Result<(), String> {
+        for (request, reply) in self.steps {
+            let mut buf = vec![0; 4096];
+            let len = stream.read(&mut buf).map_err(|e| e.to_string())?;
+            buf.truncate(len);
+
+            check_request(&request, &buf)?;
+
+            if !reply.is_empty() {
+                stream.write_all(&reply).map_err(|e| e.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_wrac(self, stream: MockStream) -> Result<(), String> {
+        let mut websocket = accept(stream).map_err(|e| e.to_string())?;
+        let mut pings = 0;
+
+        for (request, reply) in self.steps {
+            let msg = loop {
+                // pings of the client are answered by the read
+                match websocket.read().map_err(|e| e.to_string())? {
+                    Message::Pong(_) => pings -= 1,
+                    Message::Ping(_) => {}
+                    msg => break msg,
+                }
+            };
+
+            check_request(&request, &msg.into_data())?;
+
+            if !reply.is_empty() {
+                if self.pings {
+                    websocket
+                        .send(Message::Ping(b"ping".to_vec().into()))
+                        .map_err(|e| e.to_string())?;
+                    pings += 1;
+                }
+                websocket
+                    .send(Message::Binary(reply.into()))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        while pings > 0 {
+            if let Message::Pong(_) = websocket.read().map_err(|e| e.to_string())? {
+                pings -= 1;
+            }
+        }
+
+        if self.close {
+            websocket.close(None).map_err(|e| e.to_string())?;
+            websocket.flush().map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_request(expected: &[u8], actual: &[u8]) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected {:?}, got {:?}",
+            String::from_utf8_lossy(expected),
+            String::from_utf8_lossy(actual)
+        ))
+    }
+}
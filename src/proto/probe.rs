@@ -0,0 +1,184 @@
+use rand::random;
+
+use super::{
+    connect, rac, read_history_page, read_messages, send_message_auth, wrac, AuthResult,
+    ConnectOptions, Dialect, Error, RacStream, RacUrl, TlsVerification,
+};
+
+/// Size of the history tail read by the probe in bytes
+pub const PROBE_WINDOW: usize = 4096;
+
+/// What the server supports (result of [`probe`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// Server speaks WRAC on this address (may differ from the URL scheme)
+    pub wrac: bool,
+    /// Chunked reading works (None if the history is empty and it can't be checked)
+    pub chunked: Option<bool>,
+    /// Messages with auth (0x02) and registration (0x03) are supported
+    /// (None if the history has no unauthenticated messages and the auth probe is disabled)
+    pub auth: Option<bool>,
+    /// Dialect guessed by the auth support and the messages
+    pub dialect: Option<Dialect>,
+}
+
+/// Check what the server supports
+///
+/// Only the last [`PROBE_WINDOW`] bytes of the history are read. Auth is found by the marks
+/// of unauthenticated messages in them, if there are none it can be checked with a message
+/// from the random non-existent user, that server with auth refuses
+/// (server without auth may write it to the chat, so it is not done by default).
+/// If the URL scheme has wrong framing (RAC instead of WRAC or vice versa),
+/// the other one is tried on the same address
+///
+/// host - RAC URL
+/// proxy - proxy URL or proxy chain, read [`super::connect`]
+/// tls - certificate verification for racs:// and wracs://
+/// options - timeouts and limits
+/// auth_probe - send the auth message if the history has no marks
+pub fn probe(
+    host: &str,
+    proxy: Option<String>,
+    tls: &TlsVerification,
+    options: &ConnectOptions,
+    auth_probe: bool,
+) -> Result<ServerCapabilities, Error> {
+    let url = RacUrl::parse(host)?;
+
+    match probe_url(&url, proxy.clone(), tls, options, auth_probe) {
+        Ok(capabilities) => Ok(capabilities),
+        Err(
+            e @ (Error::UrlParse(_)
+            | Error::ProxyParse(_)
+            | Error::Resolve(_)
+            | Error::Connect(_)
+            | Error::Proxy(_)
+            | Error::Tls(_)
+            | Error::PinMismatch { .. }),
+        ) => Err(e),
+        Err(e) => {
+            // connection is fine, but the server doesn't understand the framing
+            let mut other = url.clone();
+            other.scheme = url.scheme.with_wrac(!url.is_wrac());
+
+            probe_url(&other, proxy, tls, options, auth_probe).map_err(|_| e)
+        }
+    }
+}
+
+fn probe_url(
+    url: &RacUrl,
+    proxy: Option<String>,
+    tls: &TlsVerification,
+    options: &ConnectOptions,
+    auth_probe: bool,
+) -> Result<ServerCapabilities, Error> {
+    let mut prober = Prober {
+        url,
+        proxy,
+        tls,
+        options,
+        stream: None,
+    };
+
+    let size = prober.request(history_size)?;
+
+    // page is read in chunked mode if the history is bigger than the window,
+    // so it is checked by the next read
+    let lines = prober
+        .request(|o| read_history_page(o, size, PROBE_WINDOW, Dialect::Rac2))
+        .map(|(lines, _)| lines)
+        .unwrap_or_default();
+
+    // read the last message in chunked mode, it must be the same as in the page
+    let tail = lines.last().map(|o| o.len() + 1).unwrap_or_default();
+
+    let chunked = match lines.last() {
+        Some(last) if size > tail => prober
+            .request(|o| read_messages(o, usize::MAX, size - tail, true, Dialect::Rac2))
+            .ok()
+            .flatten()
+            .is_some_and(|(chunk, _)| chunk == [last.clone(), String::new()])
+            .into(),
+        _ => None,
+    };
+
+    // servers with auth mark messages sent without it
+    let marked = [Dialect::Rac2, Dialect::Crab].iter().any(|dialect| {
+        lines
+            .iter()
+            .filter_map(|o| dialect.parse_line(o))
+            .any(|o| o.unauthenticated)
+    });
+
+    let auth = if marked {
+        Some(true)
+    } else if auth_probe {
+        let name = format!("bRAC-probe-{:016x}", random::<u64>());
+
+        Some(matches!(
+            prober.request(|o| send_message_auth(o, &name, "", "", Dialect::Rac2)),
+            Ok(AuthResult::UserNotFound | AuthResult::WrongPassword)
+        ))
+    } else {
+        None
+    };
+
+    let dialect = match (auth, Dialect::detect(&lines)) {
+        (Some(true), Some(Dialect::Crab)) => Some(Dialect::Crab),
+        (Some(true), _) => Some(Dialect::Rac2),
+        (Some(false), Some(Dialect::Rac1_0)) => Some(Dialect::Rac1_0),
+        (Some(false), Some(_)) => Some(Dialect::Rac1_99),
+        (_, detected) => detected,
+    };
+
+    Ok(ServerCapabilities {
+        wrac: url.is_wrac(),
+        chunked,
+        auth,
+        dialect,
+    })
+}
+
+/// Read history size without the messages
+fn history_size(stream: &mut RacStream) -> Result<usize, Error> {
+    let (_, size) = match stream {
+        RacStream::WRAC(websocket) => wrac::read_history(websocket, usize::MAX, Dialect::Rac2)?,
+        RacStream::RAC(stream) => rac::read_history(stream, usize::MAX, Dialect::Rac2)?,
+    };
+    Ok(size)
+}
+
+struct Prober<'a> {
+    url: &'a RacUrl,
+    proxy: Option<String>,
+    tls: &'a TlsVerification,
+    options: &'a ConnectOptions,
+    stream: Option<RacStream>,
+}
+
+impl Prober<'_> {
+    /// RAC server closes the connection after each request, WRAC one is reused while it works
+    fn request<T>(
+        &mut self,
+        operation: impl FnOnce(&mut RacStream) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => connect(
+                &self.url.to_string(),
+                self.proxy.clone(),
+                self.tls,
+                self.options,
+            )?,
+        };
+
+        let result = operation(&mut stream);
+
+        if result.is_ok() && self.url.is_wrac() {
+            self.stream = Some(stream);
+        }
+
+        result
+    }
+}
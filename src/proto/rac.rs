@@ -1,13 +1,12 @@
-use std::{
-    error::Error,
-    io::{Read, Write},
-};
+use std::io::{self, Read, Write};
+
+use super::{chunk_data, chunk_offset, AuthResult, Dialect, Error, RegisterResult};
 
 /// Send message
 ///
 /// stream - any stream that can be written to
 /// message - message text
-pub fn send_message(stream: &mut impl Write, message: &str) -> Result<(), Box<dyn Error>> {
+pub fn send_message(stream: &mut impl Write, message: &str) -> Result<(), Error> {
     stream.write_all(format!("\x01{message}").as_bytes())?;
     Ok(())
 }
@@ -17,20 +16,19 @@ pub fn send_message(stream: &mut impl Write, message: &str) -> Result<(), Box<dy
 /// stream - any stream that can be written to
 /// name - user name
 /// password - user password
-/// remove_null - remove null bytes on reading
+/// dialect - server dialect (error if it has no auth)
 ///
-/// returns whether the user was registered
+/// returns registration result
 pub fn register_user(
     stream: &mut (impl Write + Read),
     name: &str,
     password: &str,
-) -> Result<bool, Box<dyn Error>> {
+    dialect: Dialect,
+) -> Result<RegisterResult, Error> {
+    dialect.check_auth()?;
     stream.write_all(format!("\x03{name}\n{password}").as_bytes())?;
-    if let Ok(out) = skip_null(stream) {
-        Ok(out[0] == 0)
-    } else {
-        Ok(true)
-    }
+    let reply = read_reply(stream)?;
+    Ok(RegisterResult::from(reply.unwrap_or(0)))
 }
 
 /// Send message with auth
@@ -39,27 +37,37 @@ pub fn register_user(
 /// message - message text
 /// name - user name
 /// password - user password
-/// remove_null - remove null bytes on reading
+/// dialect - server dialect (error if it has no auth)
 ///
-/// returns 0 if the message was sent successfully
-/// returns 1 if the user does not exist
-/// returns 2 if the password is incorrect
+/// returns auth result (message is sent only if it is [`AuthResult::Sent`])
 pub fn send_message_auth(
     stream: &mut (impl Write + Read),
     name: &str,
     password: &str,
     message: &str,
-) -> Result<u8, Box<dyn Error>> {
+    dialect: Dialect,
+) -> Result<AuthResult, Error> {
+    dialect.check_auth()?;
     stream.write_all(format!("\x02{name}\n{password}\n{message}").as_bytes())?;
-    if let Ok(out) = skip_null(stream) {
-        Ok(out[0])
-    } else {
-        Ok(0)
+    let reply = read_reply(stream)?;
+    Ok(AuthResult::from(reply.unwrap_or(0)))
+}
+
+/// Read reply code to the auth or registration packet
+///
+/// Server replies only on errors and closes the connection after the packet
+///
+/// returns None if the connection was closed without a reply
+fn read_reply(stream: &mut impl Read) -> Result<Option<u8>, Error> {
+    match skip_null(stream) {
+        Ok(reply) => Ok(Some(reply[0])),
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
     }
 }
 
 /// Skip null bytes and return first non-null byte
-pub fn skip_null(stream: &mut impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn skip_null(stream: &mut impl Read) -> Result<Vec<u8>, Error> {
     loop {
         let mut buf = vec![0; 1];
         stream.read_exact(&mut buf)?;
@@ -70,7 +78,7 @@ pub fn skip_null(stream: &mut impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
 }
 
 /// remove trailing null bytes in vector
-pub fn remove_trailing_null(vec: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+pub fn remove_trailing_null(vec: &mut Vec<u8>) -> Result<(), Error> {
     while vec.ends_with(&[0]) {
         vec.remove(vec.len() - 1);
     }
@@ -81,8 +89,8 @@ pub fn remove_trailing_null(vec: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
 ///
 /// max_messages - max messages in list
 /// last_size - last returned packet size
-/// remove_null - start with skipping null bytes
 /// chunked - is chunked reading enabled
+/// dialect - server dialect
 ///
 /// returns (messages, packet size)
 pub fn read_messages(
@@ -90,7 +98,25 @@ pub fn read_messages(
     max_messages: usize,
     last_size: usize,
     chunked: bool,
-) -> Result<Option<(Vec<String>, usize)>, Box<dyn Error>> {
+    dialect: Dialect,
+) -> Result<Option<(Vec<String>, usize)>, Error> {
+    Ok(read_messages_data(stream, last_size, chunked, dialect)?
+        .map(|(data, packet_size)| (dialect.parse_messages(&data, max_messages), packet_size)))
+}
+
+/// Read messages as the server sent them
+///
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (data, packet size), data is the whole history if it is not a chunk
+pub fn read_messages_data(
+    stream: &mut (impl Read + Write),
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
     stream.write_all(&[0x00])?;
 
     let packet_size = {
@@ -99,43 +125,74 @@ pub fn read_messages(
         let len = stream.read(&mut buf)?;
         buf.truncate(len);
         data.append(&mut buf);
-        remove_trailing_null(&mut data)?;
 
-        String::from_utf8(data)?
-            .trim_matches(char::from(0))
-            .parse()?
+        dialect.parse_size(&data)?
     };
 
     if last_size == packet_size {
         return Ok(None);
     }
 
-    let to_read = if !chunked || last_size == 0 {
+    let chunked = chunked && last_size != 0;
+
+    let to_read = if !chunked {
         stream.write_all(&[0x01])?;
         packet_size
     } else {
-        stream.write_all(format!("\x02{}", last_size).as_bytes())?;
-        packet_size - last_size
+        let offset = chunk_offset(last_size, packet_size)?;
+        stream.write_all(format!("\x02{}", offset).as_bytes())?;
+        packet_size - offset
     };
 
     let mut packet_data = skip_null(stream)?;
-    let mut buf = vec![0; to_read - 1];
+    let mut buf = vec![0; to_read.saturating_sub(packet_data.len())];
     stream.read_exact(&mut buf)?;
     packet_data.append(&mut buf);
 
-    let packet_data = String::from_utf8_lossy(&packet_data).to_string();
-
-    let lines: Vec<&str> = packet_data.split("\n").collect();
-    let lines: Vec<String> = lines
-        .clone()
-        .into_iter()
-        .skip(if lines.len() >= max_messages {
-            lines.len() - max_messages
-        } else {
-            0
-        })
-        .map(|o| o.to_string())
-        .collect();
-
-    Ok(Some((lines, packet_size)))
+    if chunked {
+        packet_data = chunk_data(packet_data, last_size, packet_size)?;
+    }
+
+    Ok(Some((packet_data, packet_size)))
+}
+
+/// Read raw history bytes starting from the offset
+///
+/// offset - offset in bytes (zero is the whole history)
+/// dialect - server dialect
+///
+/// returns (data, packet size)
+pub fn read_history(
+    stream: &mut (impl Read + Write),
+    offset: usize,
+    dialect: Dialect,
+) -> Result<(Vec<u8>, usize), Error> {
+    stream.write_all(&[0x00])?;
+
+    let packet_size = {
+        let mut data = skip_null(stream)?;
+        let mut buf = vec![0; 10];
+        let len = stream.read(&mut buf)?;
+        buf.truncate(len);
+        data.append(&mut buf);
+
+        dialect.parse_size(&data)?
+    };
+
+    if offset >= packet_size {
+        return Ok((Vec::new(), packet_size));
+    }
+
+    if offset == 0 {
+        stream.write_all(&[0x01])?;
+    } else {
+        stream.write_all(format!("\x02{}", offset).as_bytes())?;
+    }
+
+    let mut data = skip_null(stream)?;
+    let mut buf = vec![0; (packet_size - offset).saturating_sub(data.len())];
+    stream.read_exact(&mut buf)?;
+    data.append(&mut buf);
+
+    Ok((data, packet_size))
 }
@@ -0,0 +1,612 @@
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tungstenite::{protocol::Role, WebSocket};
+
+use super::{ConnectOptions, Error, RacStream, RacUrl, Stream};
+
+/// Direction of the recorded data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client to server
+    Sent,
+    /// Server to client
+    Received,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => ">",
+            Direction::Received => "<",
+        }
+    }
+}
+
+/// Kind of the recorded data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Connection is opened, data is the RAC URL
+    Connect,
+    /// Bytes as they were read or written
+    Raw,
+    /// WebSocket frames (WRAC), decoded from the raw bytes with unmasked payload
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl EntryKind {
+    const ALL: [EntryKind; 8] = [
+        EntryKind::Connect,
+        EntryKind::Raw,
+        EntryKind::Continuation,
+        EntryKind::Text,
+        EntryKind::Binary,
+        EntryKind::Close,
+        EntryKind::Ping,
+        EntryKind::Pong,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Connect => "connect",
+            EntryKind::Raw => "raw",
+            EntryKind::Continuation => "continuation",
+            EntryKind::Text => "text",
+            EntryKind::Binary => "binary",
+            EntryKind::Close => "close",
+            EntryKind::Ping => "ping",
+            EntryKind::Pong => "pong",
+        }
+    }
+
+    /// Kind of the WebSocket frame by its opcode
+    pub fn from_opcode(opcode: u8) -> Option<EntryKind> {
+        match opcode {
+            0x0 => Some(EntryKind::Continuation),
+            0x1 => Some(EntryKind::Text),
+            0x2 => Some(EntryKind::Binary),
+            0x8 => Some(EntryKind::Close),
+            0x9 => Some(EntryKind::Ping),
+            0xA => Some(EntryKind::Pong),
+            _ => None,
+        }
+    }
+}
+
+/// One line of the capture file
+///
+/// Format: `<unix time in ms> #<connection> <direction> <kind> <base64 data>`,
+/// direction is `>` for sent data and `<` for received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureEntry {
+    pub time: u64,
+    /// Number of the connection in the capture (connections can be interleaved)
+    pub connection: usize,
+    pub direction: Direction,
+    pub kind: EntryKind,
+    pub data: Vec<u8>,
+}
+
+impl CaptureEntry {
+    pub fn parse(line: &str) -> Option<CaptureEntry> {
+        let mut parts = line.split(' ');
+
+        let time = parts.next()?.parse().ok()?;
+        let connection = parts.next()?.strip_prefix('#')?.parse().ok()?;
+        let direction = match parts.next()? {
+            ">" => Direction::Sent,
+            "<" => Direction::Received,
+            _ => return None,
+        };
+        let kind = parts.next()?;
+        let kind = *EntryKind::ALL.iter().find(|o| o.as_str() == kind)?;
+        let data = STANDARD.decode(parts.next().unwrap_or_default()).ok()?;
+
+        Some(CaptureEntry {
+            time,
+            connection,
+            direction,
+            kind,
+            data,
+        })
+    }
+}
+
+impl fmt::Display for CaptureEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} #{} {} {} {}",
+            self.time,
+            self.connection,
+            self.direction.as_str(),
+            self.kind.as_str(),
+            STANDARD.encode(&self.data)
+        )
+    }
+}
+
+/// Parse capture file contents, empty lines and lines starting with `#` are skipped
+pub fn parse_capture(capture: &str) -> Result<Vec<CaptureEntry>, Error> {
+    capture
+        .lines()
+        .enumerate()
+        .filter(|(_, o)| !o.trim().is_empty() && !o.starts_with('#'))
+        .map(|(i, o)| {
+            CaptureEntry::parse(o.trim())
+                .ok_or(Error::Protocol(format!("invalid capture line {}", i + 1)))
+        })
+        .collect()
+}
+
+/// Capture file writer, shared by all recorded connections
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("connections", &self.connections)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    pub fn new(writer: impl Write + Send + 'static) -> Recorder {
+        Recorder {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Open capture file, new entries are appended
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Recorder> {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(Recorder::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+
+    /// Write the entry, recording errors are ignored so they don't break the connection
+    pub fn record(&self, entry: &CaptureEntry) {
+        let mut writer = self.writer.lock().unwrap();
+        let _ = writeln!(writer, "{entry}");
+        let _ = writer.flush();
+    }
+
+    /// Record new connection
+    ///
+    /// returns connection number
+    fn connect(&self, url: &str) -> usize {
+        let connection = self.connections.fetch_add(1, Ordering::SeqCst) + 1;
+
+        self.record(&CaptureEntry {
+            time: now(),
+            connection,
+            direction: Direction::Sent,
+            kind: EntryKind::Connect,
+            data: url.as_bytes().to_vec(),
+        });
+
+        connection
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Stream wrapper that records all read and written bytes (and WebSocket frames for WRAC)
+#[derive(Debug)]
+pub struct RecordingStream<S> {
+    inner: S,
+    recorder: Recorder,
+    connection: usize,
+    /// Decoders of the sent and received frames (WRAC only)
+    frames: Option<(FrameDecoder, FrameDecoder)>,
+}
+
+impl<S> RecordingStream<S> {
+    /// Start recording of the connection
+    ///
+    /// url - RAC URL of the connection, that is written to the capture
+    /// wrac - decode WebSocket frames after the handshake
+    pub fn new(inner: S, recorder: Recorder, url: &str, wrac: bool) -> RecordingStream<S> {
+        RecordingStream {
+            connection: recorder.connect(url),
+            inner,
+            recorder,
+            frames: wrac.then(Default::default),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let time = now();
+        let mut entry = CaptureEntry {
+            time,
+            connection: self.connection,
+            direction,
+            kind: EntryKind::Raw,
+            data: data.to_vec(),
+        };
+        self.recorder.record(&entry);
+
+        let frames = match (&mut self.frames, direction) {
+            (Some((sent, _)), Direction::Sent) => sent.push(data),
+            (Some((_, received)), Direction::Received) => received.push(data),
+            (None, _) => return,
+        };
+
+        for (opcode, payload) in frames {
+            let Some(kind) = EntryKind::from_opcode(opcode) else {
+                continue;
+            };
+            entry.kind = kind;
+            entry.data = payload;
+            self.recorder.record(&entry);
+        }
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.record(Direction::Received, &buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.record(Direction::Sent, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for RecordingStream<Box<dyn Stream>> {
+    fn set_read_timeout(&self, timeout: Duration) {
+        self.inner.set_read_timeout(timeout);
+    }
+    fn set_write_timeout(&self, timeout: Duration) {
+        self.inner.set_write_timeout(timeout);
+    }
+}
+
+/// Decoder of the WebSocket frames from one direction of the connection
+#[derive(Debug, Default)]
+struct FrameDecoder {
+    /// HTTP handshake is skipped
+    handshake_done: bool,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Add bytes
+    ///
+    /// returns complete frames: (opcode, unmasked payload)
+    fn push(&mut self, data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        self.buffer.extend_from_slice(data);
+
+        if !self.handshake_done {
+            let Some(pos) = self.buffer.windows(4).position(|o| o == b"\r\n\r\n") else {
+                return Vec::new();
+            };
+            self.buffer.drain(..pos + 4);
+            self.handshake_done = true;
+        }
+
+        let mut frames = Vec::new();
+        while let Some((frame, len)) = parse_frame(&self.buffer) {
+            frames.push(frame);
+            self.buffer.drain(..len);
+        }
+        frames
+    }
+}
+
+/// Parse one WebSocket frame
+///
+/// returns ((opcode, unmasked payload), frame length) or None if the frame is not complete
+fn parse_frame(data: &[u8]) -> Option<((u8, Vec<u8>), usize)> {
+    let opcode = data.first()? & 0x0F;
+    let masked = data.get(1)? & 0x80 != 0;
+
+    let (len, mut pos) = match data[1] & 0x7F {
+        126 => (
+            u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        127 => (
+            usize::try_from(u64::from_be_bytes(data.get(2..10)?.try_into().ok()?)).ok()?,
+            10,
+        ),
+        len => (len as usize, 2),
+    };
+
+    let mask: Option<[u8; 4]> = if masked {
+        pos += 4;
+        Some(data.get(pos - 4..pos)?.try_into().ok()?)
+    } else {
+        None
+    };
+
+    let end = pos.checked_add(len)?;
+    let mut payload = data.get(pos..end)?.to_vec();
+
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+
+    Some(((opcode, payload), end))
+}
+
+/// Recorded connections, that are replayed instead of connecting to the server
+///
+/// ```no_run
+/// use bRAC::proto::{read_messages, record::Replay, ConnectOptions, Dialect};
+///
+/// let mut replay = Replay::load("capture.log").unwrap();
+/// let mut stream = replay.connect(&ConnectOptions::default()).unwrap();
+///
+/// let (messages, _) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2).unwrap().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    /// (RAC URL, entries) in the order of connecting
+    connections: VecDeque<(String, Vec<CaptureEntry>)>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, Error> {
+        Replay::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(capture: &str) -> Result<Replay, Error> {
+        Ok(Replay::from_entries(parse_capture(capture)?))
+    }
+
+    pub fn from_entries(entries: Vec<CaptureEntry>) -> Replay {
+        let mut connections: Vec<(usize, String, Vec<CaptureEntry>)> = Vec::new();
+
+        for entry in entries {
+            if entry.kind == EntryKind::Connect {
+                let url = String::from_utf8_lossy(&entry.data).to_string();
+                connections.push((entry.connection, url, Vec::new()));
+            } else if let Some((_, _, entries)) = connections
+                .iter_mut()
+                .rev()
+                .find(|o| o.0 == entry.connection)
+            {
+                entries.push(entry);
+            }
+        }
+
+        Replay {
+            connections: connections
+                .into_iter()
+                .map(|(_, url, entries)| (url, entries))
+                .collect(),
+        }
+    }
+
+    /// Number of connections left
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Take the next recorded connection
+    ///
+    /// returns RAC URL and the stream with the received data (WebSocket handshake is skipped for WRAC)
+    pub fn next_stream(&mut self) -> Result<(RacUrl, ReplayStream), Error> {
+        let (url, entries) = self
+            .connections
+            .pop_front()
+            .ok_or(Error::Protocol("no more recorded connections".to_string()))?;
+        let url = RacUrl::parse(&url)?;
+
+        let mut incoming: VecDeque<Vec<u8>> = entries
+            .into_iter()
+            .filter(|o| o.kind == EntryKind::Raw && o.direction == Direction::Received)
+            .map(|o| o.data)
+            .collect();
+
+        // handshake can't be replayed, the key of the client is random
+        if url.is_wrac() {
+            let mut head = Vec::new();
+            while let Some(mut chunk) = incoming.pop_front() {
+                head.extend_from_slice(&chunk);
+                if let Some(pos) = head.windows(4).position(|o| o == b"\r\n\r\n") {
+                    let rest = chunk.split_off(chunk.len() - (head.len() - pos - 4));
+                    if !rest.is_empty() {
+                        incoming.push_front(rest);
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok((url, ReplayStream { incoming }))
+    }
+
+    /// Open the next recorded connection
+    ///
+    /// options - WebSocket limits are used for WRAC
+    ///
+    /// returns stream, that reads the received data and discards everything written
+    pub fn connect(&mut self, options: &ConnectOptions) -> Result<RacStream, Error> {
+        let (url, stream) = self.next_stream()?;
+        let stream: Box<dyn Stream> = Box::new(stream);
+
+        if url.is_wrac() {
+            Ok(RacStream::WRAC(WebSocket::from_raw_socket(
+                stream,
+                Role::Client,
+                Some(options.websocket_config()),
+            )))
+        } else {
+            Ok(RacStream::RAC(stream))
+        }
+    }
+}
+
+/// Stream of the recorded data, every read returns bytes of one recorded read
+#[derive(Debug, Default)]
+pub struct ReplayStream {
+    incoming: VecDeque<Vec<u8>>,
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(chunk) = self.incoming.front_mut() else {
+            return Ok(0);
+        };
+
+        let len = buf.len().min(chunk.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+        chunk.drain(..len);
+        if chunk.is_empty() {
+            self.incoming.pop_front();
+        }
+
+        Ok(len)
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for ReplayStream {
+    fn set_read_timeout(&self, _: Duration) {}
+    fn set_write_timeout(&self, _: Duration) {}
+}
+
+#[cfg(feature = "async")]
+mod r#async {
+    use std::{
+        io::{self, Read},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::{Direction, RecordingStream, ReplayStream};
+
+    impl<S: AsyncRead + Unpin> AsyncRead for RecordingStream<S> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let filled = buf.filled().len();
+            let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+            if let Poll::Ready(Ok(())) = poll {
+                self.record(Direction::Received, &buf.filled()[filled..]);
+            }
+            poll
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for RecordingStream<S> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+            if let Poll::Ready(Ok(len)) = poll {
+                self.record(Direction::Sent, &buf[..len]);
+            }
+            poll
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    impl AsyncRead for ReplayStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let len = self.read(buf.initialize_unfilled())?;
+            buf.advance(len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for ReplayStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{IpAddr, TcpListener},
+    sync::{Arc, RwLock},
+    thread,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use chrono::Local;
+use tungstenite::{accept, Message};
+
+use super::{AuthResult, Error, RacUrl};
+
+/// Max size of one incoming RAC packet, longer packets are cut (see [`read_packet`])
+const MAX_PACKET_SIZE: usize = 4096;
+
+/// Storage of the chat history
+///
+/// History is a byte string where every message ends with `\n`,
+/// offsets and sizes are in bytes (as in the protocol)
+pub trait MessageStorage: Send + Sync {
+    /// Size of all messages in bytes
+    fn size(&self) -> usize;
+
+    /// Read messages starting from the offset
+    fn read(&self, offset: usize) -> Vec<u8>;
+
+    /// Add message to the end of the history
+    fn push(&self, message: &str);
+}
+
+/// Database of registered users
+pub trait UserDatabase: Send + Sync {
+    /// Is there a user with this name
+    fn exists(&self, name: &str) -> bool;
+
+    /// Check password of the existing user
+    fn check_password(&self, name: &str, password: &str) -> bool;
+
+    /// Add new user, returns false if the name is already taken
+    fn register(&self, name: &str, password: &str) -> bool;
+}
+
+/// In-memory chat history
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: RwLock<Vec<u8>>,
+}
+
+impl MessageStorage for MemoryStorage {
+    fn size(&self) -> usize {
+        self.data.read().unwrap().len()
+    }
+
+    fn read(&self, offset: usize) -> Vec<u8> {
+        let data = self.data.read().unwrap();
+        data[offset.min(data.len())..].to_vec()
+    }
+
+    fn push(&self, message: &str) {
+        let mut data = self.data.write().unwrap();
+        data.extend_from_slice(message.as_bytes());
+        data.push(b'\n');
+    }
+}
+
+/// In-memory user database
+#[derive(Default)]
+pub struct MemoryUserDatabase {
+    users: RwLock<HashMap<String, String>>,
+}
+
+impl UserDatabase for MemoryUserDatabase {
+    fn exists(&self, name: &str) -> bool {
+        self.users.read().unwrap().contains_key(name)
+    }
+
+    fn check_password(&self, name: &str, password: &str) -> bool {
+        self.users.read().unwrap().get(name).map(|o| o.as_str()) == Some(password)
+    }
+
+    fn register(&self, name: &str, password: &str) -> bool {
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(name) {
+            return false;
+        }
+        users.insert(name.to_string(), password.to_string());
+        true
+    }
+}
+
+/// Embedded RACv2 / WRACv2.0 server
+///
+/// Only plain `rac://` and `wrac://` (also `+unix`) listeners are created by [`Server::listen`],
+/// for SSL wrap the streams yourself and pass them to `handle_*_connection`
+pub struct Server {
+    pub storage: Arc<dyn MessageStorage>,
+    pub users: Arc<dyn UserDatabase>,
+}
+
+impl Server {
+    pub fn new(storage: Arc<dyn MessageStorage>, users: Arc<dyn UserDatabase>) -> Server {
+        Server { storage, users }
+    }
+
+    /// Server with in-memory storage and user database
+    pub fn in_memory() -> Server {
+        Server::new(
+            Arc::new(MemoryStorage::default()),
+            Arc::new(MemoryUserDatabase::default()),
+        )
+    }
+
+    /// Bind listener by RAC URL and accept connections in the current thread
+    ///
+    /// url - RAC URL, example: "rac://127.0.0.1", "wrac://0.0.0.0:12345", "rac+unix:///run/rac.sock"
+    pub fn listen(self: Arc<Self>, url: &str) -> Result<(), Error> {
+        let url = RacUrl::parse(url)?;
+
+        if url.is_ssl() {
+            // ssl listeners are not supported
+            return Err(Error::Io(io::ErrorKind::Unsupported.into()));
+        }
+
+        if url.is_unix() {
+            return self.listen_unix(&url.address(), url.is_wrac());
+        }
+
+        self.run(TcpListener::bind(url.address())?, url.is_wrac())
+    }
+
+    #[cfg(unix)]
+    fn listen_unix(self: Arc<Self>, path: &str, wrac: bool) -> Result<(), Error> {
+        self.run_unix(UnixListener::bind(path)?, wrac)
+    }
+
+    #[cfg(not(unix))]
+    fn listen_unix(self: Arc<Self>, _: &str, _: bool) -> Result<(), Error> {
+        Err(Error::Io(io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Accept connections from the Unix socket listener in the current thread (messages have no ip)
+    #[cfg(unix)]
+    pub fn run_unix(self: Arc<Self>, listener: UnixListener, wrac: bool) -> Result<(), Error> {
+        for stream in listener.incoming() {
+            let mut stream = stream?;
+            let server = self.clone();
+
+            thread::spawn(move || {
+                let _ = if wrac {
+                    server.handle_wrac_connection(stream, None)
+                } else {
+                    server.handle_rac_connection(&mut stream, None)
+                };
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Accept connections from the listener in the current thread, one thread per connection
+    pub fn run(self: Arc<Self>, listener: TcpListener, wrac: bool) -> Result<(), Error> {
+        for stream in listener.incoming() {
+            let mut stream = stream?;
+            let server = self.clone();
+
+            thread::spawn(move || {
+                let addr = stream.peer_addr().ok().map(|o| o.ip());
+                let _ = if wrac {
+                    server.handle_wrac_connection(stream, addr)
+                } else {
+                    server.handle_rac_connection(&mut stream, addr)
+                };
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Format and save message to the storage
+    ///
+    /// name - checked name of the sender, None marks the message with `(UNREGISTERED)`
+    fn add_message(&self, addr: Option<IpAddr>, name: Option<&str>, message: &str) {
+        let mut line = format!("[{}] ", Local::now().format("%d.%m.%Y %H:%M"));
+        if let Some(addr) = addr {
+            line.push_str(&format!("{{{addr}}} "));
+        }
+        match name {
+            Some(name) => line.push_str(&format!("<{name}> ")),
+            None => line.push_str("(UNREGISTERED) "),
+        }
+        line.push_str(message);
+        self.storage.push(&line);
+    }
+
+    /// Handle authorized message packet body (without 0x02)
+    ///
+    fn on_message_auth(&self, addr: Option<IpAddr>, data: &str) -> AuthResult {
+        let mut parts = data.splitn(3, "\n");
+        let name = parts.next().unwrap_or_default();
+        let password = parts.next().unwrap_or_default();
+        let message = parts.next().unwrap_or_default();
+
+        if !self.users.exists(name) {
+            AuthResult::UserNotFound
+        } else if !self.users.check_password(name, password) {
+            AuthResult::WrongPassword
+        } else {
+            self.add_message(addr, Some(name), message);
+            AuthResult::Sent
+        }
+    }
+
+    /// Handle registration packet body (without 0x03)
+    ///
+    /// returns whether the user was registered
+    fn on_register(&self, data: &str) -> bool {
+        let (name, password) = data.split_once("\n").unwrap_or((data, ""));
+        !name.is_empty() && self.users.register(name, password)
+    }
+
+    /// Handle chunked reading packet body (without 0x00 0x02 or 0x02)
+    fn read_chunk(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let last_size: usize = String::from_utf8_lossy(data)
+            .trim_matches(char::from(0))
+            .trim()
+            .parse()?;
+        Ok(self.storage.read(last_size))
+    }
+
+    /// Handle one packet and return the response
+    ///
+    /// Reading packets are in WRAC format (0x00 0x01 and 0x00 0x02)
+    fn on_packet(&self, addr: Option<IpAddr>, packet: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(match packet {
+            [0x00] => Some(self.storage.size().to_string().into_bytes()),
+            [0x00, 0x01, ..] => Some(self.storage.read(0)),
+            [0x00, 0x02, data @ ..] => Some(self.read_chunk(data)?),
+            [0x01, data @ ..] => {
+                self.add_message(addr, None, &String::from_utf8_lossy(data));
+                None
+            }
+            [0x02, data @ ..] => match self.on_message_auth(addr, &String::from_utf8_lossy(data)) {
+                AuthResult::Sent => None,
+                result => Some(vec![result.code()]),
+            },
+            [0x03, data @ ..] => {
+                if self.on_register(&String::from_utf8_lossy(data)) {
+                    None
+                } else {
+                    Some(vec![0x01])
+                }
+            }
+            _ => None,
+        })
+    }
+
+    /// Handle one RAC connection (client closes it after each request)
+    ///
+    /// addr - client address, that will be written in messages
+    pub fn handle_rac_connection(
+        &self,
+        stream: &mut (impl Read + Write),
+        addr: Option<IpAddr>,
+    ) -> Result<(), Error> {
+        let mut packet = read_packet(stream)?;
+
+        if packet.first() == Some(&0x00) {
+            // in RAC reading packet is sent after getting the size in the same connection
+            stream.write_all(self.storage.size().to_string().as_bytes())?;
+            stream.flush()?;
+
+            let next = if packet.len() > 1 {
+                packet.split_off(1)
+            } else {
+                read_packet(stream)?
+            };
+
+            if next.is_empty() {
+                return Ok(());
+            }
+
+            packet.extend(next);
+        }
+
+        if let Some(response) = self.on_packet(addr, &packet)? {
+            stream.write_all(&response)?;
+        }
+
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Handle WRAC connection until it is closed
+    ///
+    /// addr - client address, that will be written in messages
+    pub fn handle_wrac_connection(
+        &self,
+        stream: impl Read + Write,
+        addr: Option<IpAddr>,
+    ) -> Result<(), Error> {
+        let mut websocket = accept(stream)?;
+
+        loop {
+            let msg = match websocket.read() {
+                Ok(msg) => msg,
+                Err(tungstenite::Error::ConnectionClosed) => break Ok(()),
+                Err(e) => break Err(e.into()),
+            };
+
+            if !msg.is_binary() {
+                continue;
+            }
+
+            if let Some(response) = self.on_packet(addr, &msg.into_data())? {
+                websocket.send(Message::Binary(response.into()))?;
+            }
+        }
+    }
+}
+
+/// Read one RAC packet
+///
+/// RAC has no framing and the client keeps the connection open while waiting for the reply,
+/// so the packet is what one read returns: packets longer than [`MAX_PACKET_SIZE`]
+/// and packets split by the network are cut
+fn read_packet(stream: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    let len = stream.read(&mut buf)?;
+    buf.truncate(len);
+    Ok(buf)
+}
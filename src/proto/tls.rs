@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use native_tls::{Certificate, TlsConnector};
+use sha2::{Digest, Sha256};
+
+use super::Error;
+
+/// TLS certificate verification mode
+#[derive(
+    serde::Serialize, serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Verify certificate chain and host name
+    Strict,
+    /// Trust on first use: pin certificate fingerprint on the first connection
+    Tofu,
+    /// Accept any certificate
+    Insecure,
+}
+
+/// Storage of pinned certificate fingerprints
+pub trait PinStore: Send + Sync {
+    /// Pinned fingerprint of the host
+    fn pinned(&self, host: &str) -> Option<String>;
+
+    /// Pin fingerprint of the host (replaces the old one)
+    ///
+    /// returns error if the pin couldn't be saved
+    fn pin(&self, host: &str, fingerprint: &str) -> Result<(), Error>;
+}
+
+/// TLS verification used on connect
+#[derive(Clone)]
+pub enum TlsVerification {
+    Strict,
+    Tofu(Arc<dyn PinStore>),
+    Insecure,
+}
+
+impl TlsVerification {
+    pub fn new(mode: TlsMode, pins: Arc<dyn PinStore>) -> TlsVerification {
+        match mode {
+            TlsMode::Strict => TlsVerification::Strict,
+            TlsMode::Tofu => TlsVerification::Tofu(pins),
+            TlsMode::Insecure => TlsVerification::Insecure,
+        }
+    }
+}
+
+/// Pinned fingerprints in a file, one `host fingerprint` per line
+pub struct KnownHosts {
+    path: PathBuf,
+    hosts: RwLock<HashMap<String, String>>,
+}
+
+impl KnownHosts {
+    /// Load known hosts file (missing file is empty)
+    pub fn load(path: PathBuf) -> KnownHosts {
+        let hosts = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|o| o.split_once(" "))
+            .map(|(host, fingerprint)| (host.to_string(), fingerprint.trim().to_string()))
+            .collect();
+
+        KnownHosts {
+            path,
+            hosts: RwLock::new(hosts),
+        }
+    }
+
+    fn save(&self, hosts: &HashMap<String, String>) -> io::Result<()> {
+        let mut text = String::new();
+        for (host, fingerprint) in hosts {
+            text.push_str(&format!("{host} {fingerprint}\n"));
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, text)
+    }
+}
+
+impl PinStore for KnownHosts {
+    fn pinned(&self, host: &str) -> Option<String> {
+        self.hosts.read().unwrap().get(host).cloned()
+    }
+
+    /// Pin is kept in memory even if the file couldn't be written
+    fn pin(&self, host: &str, fingerprint: &str) -> Result<(), Error> {
+        let mut hosts = self.hosts.write().unwrap();
+        hosts.insert(host.to_string(), fingerprint.to_string());
+        self.save(&hosts).map_err(Error::PinSave)
+    }
+}
+
+/// SHA-256 fingerprint of the certificate, `AB:CD:...`
+pub fn fingerprint(cert: &Certificate) -> Result<String, Error> {
+    Ok(Sha256::digest(cert.to_der()?)
+        .iter()
+        .map(|o| format!("{o:02X}"))
+        .collect::<Vec<String>>()
+        .join(":"))
+}
+
+/// Build TLS connector for the verification mode
+///
+/// Strict mode verifies certificates by the connector itself,
+/// TOFU accepts any certificate here and checks it later in [`check_pin`]
+pub fn build_connector(verification: &TlsVerification) -> Result<TlsConnector, Error> {
+    let mut builder = TlsConnector::builder();
+    if !matches!(verification, TlsVerification::Strict) {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// Check server certificate against the pinned fingerprint (only for TOFU)
+///
+/// host - host with port, used as pin key
+/// cert - server certificate
+///
+/// returns [`Error::PinSave`] if the first certificate of the host couldn't be pinned
+pub fn check_pin(
+    verification: &TlsVerification,
+    host: &str,
+    cert: Option<Certificate>,
+) -> Result<(), Error> {
+    let TlsVerification::Tofu(pins) = verification else {
+        return Ok(());
+    };
+
+    let cert = cert.ok_or(Error::Protocol("server sent no certificate".to_string()))?;
+    let actual = fingerprint(&cert)?;
+
+    match pins.pinned(host) {
+        Some(expected) if expected != actual => Err(Error::PinMismatch {
+            host: host.to_string(),
+            expected,
+            actual,
+        }),
+        Some(_) => Ok(()),
+        None => pins.pin(host, &actual),
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::Request,
+    http::{header::HOST, HeaderName, HeaderValue},
+};
+
+use super::Error;
+
+/// RAC URL protocol (read docs/url.md)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RacScheme {
+    Rac,
+    Racs,
+    Wrac,
+    Wracs,
+}
+
+impl RacScheme {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            RacScheme::Rac => 42666,
+            RacScheme::Racs => 42667,
+            RacScheme::Wrac => 52666,
+            RacScheme::Wracs => 52667,
+        }
+    }
+
+    pub fn is_ssl(&self) -> bool {
+        matches!(self, RacScheme::Racs | RacScheme::Wracs)
+    }
+
+    pub fn is_wrac(&self) -> bool {
+        matches!(self, RacScheme::Wrac | RacScheme::Wracs)
+    }
+
+    /// The same scheme with or without WebSocket (SSL is kept)
+    pub fn with_wrac(&self, wrac: bool) -> RacScheme {
+        match (self.is_ssl(), wrac) {
+            (false, false) => RacScheme::Rac,
+            (true, false) => RacScheme::Racs,
+            (false, true) => RacScheme::Wrac,
+            (true, true) => RacScheme::Wracs,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RacScheme::Rac => "rac",
+            RacScheme::Racs => "racs",
+            RacScheme::Wrac => "wrac",
+            RacScheme::Wracs => "wracs",
+        }
+    }
+}
+
+impl FromStr for RacScheme {
+    type Err = Error;
+
+    fn from_str(scheme: &str) -> Result<Self, Self::Err> {
+        match scheme.to_lowercase().as_str() {
+            "rac" => Ok(RacScheme::Rac),
+            "racs" => Ok(RacScheme::Racs),
+            "wrac" => Ok(RacScheme::Wrac),
+            "wracs" => Ok(RacScheme::Wracs),
+            _ => Err(Error::UrlParse(format!("unknown scheme {scheme}"))),
+        }
+    }
+}
+
+impl fmt::Display for RacScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Host of the RAC URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RacHost {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Domain(String),
+    /// Path of the Unix domain socket (`rac+unix://` and `wrac+unix://`)
+    Unix(String),
+}
+
+impl RacHost {
+    /// Host without brackets, for TLS server name
+    pub fn name(&self) -> String {
+        match self {
+            RacHost::Ipv4(ip) => ip.to_string(),
+            RacHost::Ipv6(ip) => ip.to_string(),
+            RacHost::Domain(domain) => domain.clone(),
+            RacHost::Unix(path) => path.clone(),
+        }
+    }
+}
+
+impl FromStr for RacHost {
+    type Err = Error;
+
+    /// `127.0.0.1`, `[::1]`, `::1` or `example.com`
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        let bare = host
+            .strip_prefix("[")
+            .and_then(|o| o.strip_suffix("]"))
+            .unwrap_or(host);
+
+        if let Ok(ip) = bare.parse() {
+            Ok(RacHost::Ipv6(ip))
+        } else if let Ok(ip) = host.parse() {
+            Ok(RacHost::Ipv4(ip))
+        } else if host.is_empty() || host.contains([':', '[', ']', ' ', '@']) {
+            Err(Error::UrlParse(format!("invalid host {host}")))
+        } else {
+            Ok(RacHost::Domain(host.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for RacHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RacHost::Ipv4(ip) => write!(f, "{ip}"),
+            RacHost::Ipv6(ip) => write!(f, "[{ip}]"),
+            RacHost::Domain(domain) => f.write_str(domain),
+            RacHost::Unix(path) => f.write_str(path),
+        }
+    }
+}
+
+/// Parsed RAC URL: `<protocol>://<address>[:<port>][/path]` or `<protocol>+unix://<socket path>`
+///
+/// `127.0.0.1` -> `rac://127.0.0.1:42666` \
+/// `wracs://[::1]/chat` -> `wracs://[::1]:52667/chat` \
+/// `::1` -> `rac://[::1]:42666` \
+/// `wrac+unix:///run/rac.sock` -> WRAC over the `/run/rac.sock` socket (port is zero)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RacUrl {
+    pub scheme: RacScheme,
+    pub host: RacHost,
+    pub port: u16,
+    /// Path with leading `/`, or empty
+    pub path: String,
+}
+
+impl RacUrl {
+    pub fn parse(url: &str) -> Result<RacUrl, Error> {
+        url.parse()
+    }
+
+    /// `host:port` for connecting (IPv6 is in brackets), socket path for Unix sockets
+    pub fn address(&self) -> String {
+        match &self.host {
+            RacHost::Unix(path) => path.clone(),
+            host => format!("{}:{}", host, self.port),
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        matches!(self.host, RacHost::Unix(_))
+    }
+
+    pub fn is_ssl(&self) -> bool {
+        self.scheme.is_ssl()
+    }
+
+    pub fn is_wrac(&self) -> bool {
+        self.scheme.is_wrac()
+    }
+
+    /// WebSocket URL for the WRAC handshake
+    ///
+    /// `wracs://example.com/chat?room=1` -> `wss://example.com:52667/chat?room=1`
+    pub fn websocket_url(&self) -> String {
+        let path = self.path.split('#').next().unwrap_or_default();
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{path}")
+        };
+        let scheme = if self.is_ssl() { "wss" } else { "ws" };
+
+        format!("{scheme}://{}{path}", self.http_host())
+    }
+
+    /// Host for the WebSocket handshake (there is no host in Unix socket URLs)
+    fn http_host(&self) -> String {
+        if self.is_unix() {
+            "localhost".to_string()
+        } else {
+            self.address()
+        }
+    }
+
+    /// WebSocket handshake request for the WRAC connection
+    ///
+    /// headers - additional headers, example: `[("Origin", "https://example.com")]`
+    ///
+    /// Host header has no port if it is default for the websocket scheme (80 or 443),
+    /// so servers behind reverse proxies can match it
+    pub fn websocket_request(&self, headers: &[(String, String)]) -> Result<Request, Error> {
+        let mut request = self.websocket_url().into_client_request()?;
+
+        let http_port = if self.is_ssl() { 443 } else { 80 };
+        let host = if self.port == http_port {
+            self.host.to_string()
+        } else {
+            self.http_host()
+        };
+
+        let request_headers = request.headers_mut();
+        request_headers.insert(HOST, header_value(&host)?);
+
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::UrlParse(format!("invalid header name {name}")))?;
+            request_headers.insert(name, header_value(value)?);
+        }
+
+        Ok(request)
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value)
+        .map_err(|_| Error::UrlParse(format!("invalid header value {value}")))
+}
+
+impl FromStr for RacUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("rac", url));
+
+        if let Some(scheme) = scheme
+            .to_lowercase()
+            .strip_suffix("+unix")
+            .map(RacScheme::from_str)
+        {
+            let scheme = scheme?;
+
+            if scheme.is_ssl() {
+                return Err(Error::UrlParse(format!(
+                    "no ssl over unix sockets in {url}"
+                )));
+            }
+            if rest.is_empty() {
+                return Err(Error::UrlParse(format!("no socket path in {url}")));
+            }
+
+            return Ok(RacUrl {
+                scheme,
+                host: RacHost::Unix(rest.to_string()),
+                port: 0,
+                path: String::new(),
+            });
+        }
+
+        let scheme: RacScheme = scheme.parse()?;
+
+        let (authority, path) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+
+        let (host, port) = if authority.starts_with("[") {
+            let end = authority
+                .find("]")
+                .ok_or(Error::UrlParse(format!("unclosed bracket in {url}")))?;
+            match &authority[end + 1..] {
+                "" => (&authority[..=end], None),
+                port => (
+                    &authority[..=end],
+                    Some(
+                        port.strip_prefix(":")
+                            .ok_or(Error::UrlParse(format!("invalid port in {url}")))?,
+                    ),
+                ),
+            }
+        } else if authority.matches(":").count() > 1 {
+            (authority, None) // bare ipv6
+        } else if let Some((host, port)) = authority.split_once(":") {
+            (host, Some(port))
+        } else {
+            (authority, None)
+        };
+
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| Error::UrlParse(format!("invalid port in {url}")))?,
+            None => scheme.default_port(),
+        };
+
+        Ok(RacUrl {
+            scheme,
+            host: host.parse()?,
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RacUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_unix() {
+            return write!(f, "{}+unix://{}", self.scheme, self.host);
+        }
+
+        write!(f, "{}://{}", self.scheme, self.host)?;
+        if self.port != self.scheme.default_port() {
+            write!(f, ":{}", self.port)?;
+        }
+        f.write_str(&self.path)
+    }
+}
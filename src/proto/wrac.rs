@@ -1,17 +1,108 @@
-use std::{
-    error::Error,
-    io::{Read, Write},
-};
-use tungstenite::{Message, WebSocket};
+use std::io::{self, Read, Write};
+use tungstenite::{error::ProtocolError, Message, WebSocket};
+
+use super::{chunk_data, chunk_offset, AuthResult, Dialect, Error, RegisterResult};
+
+/// Read next data message, control frames are skipped (pings are answered with pongs)
+///
+/// returns error if the server closed the connection
+fn read_message(stream: &mut WebSocket<impl Write + Read>) -> Result<Message, Error> {
+    loop {
+        match stream.read()? {
+            // pong is queued by the read and sent by the flush
+            Message::Ping(_) => stream.flush()?,
+            Message::Pong(_) | Message::Frame(_) => {}
+            Message::Close(_) => {
+                return Err(Error::WebSocket(Box::new(
+                    tungstenite::Error::ConnectionClosed,
+                )))
+            }
+            msg => return Ok(msg),
+        }
+    }
+}
+
+/// Read next binary message
+fn read_binary(stream: &mut WebSocket<impl Write + Read>) -> Result<Vec<u8>, Error> {
+    let msg = read_message(stream)?;
+    if !msg.is_binary() {
+        return Err(Error::Protocol("msg is not binary".to_string()));
+    }
+    Ok(msg.into_data().to_vec())
+}
+
+/// Send ping to keep the connection alive, pong is skipped by the next read
+pub fn send_ping(stream: &mut WebSocket<impl Write + Read>) -> Result<(), Error> {
+    stream.send(Message::Ping(Default::default()))?;
+    Ok(())
+}
+
+/// Get response code from the server reply (empty or non-binary reply is 0)
+fn response_code(msg: Message) -> u8 {
+    if msg.is_binary() {
+        *msg.into_data().first().unwrap_or(&0)
+    } else {
+        0
+    }
+}
+
+/// Is the error a closed connection (close frame or EOF)
+pub(crate) fn is_closed(e: &Error) -> bool {
+    match e {
+        Error::WebSocket(e) => matches!(
+            **e,
+            tungstenite::Error::ConnectionClosed
+                | tungstenite::Error::AlreadyClosed
+                | tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
+        ),
+        Error::Io(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Send auth or registration packet and read the reply code
+///
+/// Server replies only on errors and keeps the connection open, so the size request
+/// is sent after the packet: if the first reply is the size, there was no reply to the packet
+/// (reply codes are not digits)
+///
+/// returns None if there was no reply or the server closed the connection without it
+fn request_reply(
+    stream: &mut WebSocket<impl Write + Read>,
+    packet: String,
+    dialect: Dialect,
+) -> Result<Option<u8>, Error> {
+    stream.write(Message::Binary(packet.into_bytes().into()))?;
+    stream.write(Message::Binary(vec![0x00].into()))?;
+    stream.flush()?;
+
+    let msg = match read_message(stream) {
+        Ok(msg) => msg,
+        Err(e) if is_closed(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if let Message::Binary(data) = &msg {
+        if dialect.parse_size(data).is_ok() {
+            return Ok(None);
+        }
+    }
+
+    // skip the size reply
+    match read_message(stream) {
+        Ok(_) => {}
+        Err(e) if is_closed(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(Some(response_code(msg)))
+}
 
 /// Send message
 ///
 /// stream - any stream that can be written to
 /// message - message text
-pub fn send_message(
-    stream: &mut WebSocket<impl Write + Read>,
-    message: &str,
-) -> Result<(), Box<dyn Error>> {
+pub fn send_message(stream: &mut WebSocket<impl Write + Read>, message: &str) -> Result<(), Error> {
     stream.write(Message::Binary(
         format!("\x01{message}").as_bytes().to_vec().into(),
     ))?;
@@ -24,22 +115,18 @@ pub fn send_message(
 /// stream - any stream that can be written to
 /// name - user name
 /// password - user password
+/// dialect - server dialect (error if it has no auth)
 ///
-/// returns whether the user was registered
+/// returns registration result
 pub fn register_user(
     stream: &mut WebSocket<impl Write + Read>,
     name: &str,
     password: &str,
-) -> Result<bool, Box<dyn Error>> {
-    stream.write(Message::Binary(
-        format!("\x03{name}\n{password}").as_bytes().to_vec().into(),
-    ))?;
-    stream.flush()?;
-    if let Ok(msg) = stream.read() {
-        Ok(!msg.is_binary() || msg.into_data().get(0).unwrap_or(&0) == &0)
-    } else {
-        Ok(true)
-    }
+    dialect: Dialect,
+) -> Result<RegisterResult, Error> {
+    dialect.check_auth()?;
+    let reply = request_reply(stream, format!("\x03{name}\n{password}"), dialect)?;
+    Ok(RegisterResult::from(reply.unwrap_or(0)))
 }
 
 /// Send message with auth
@@ -48,32 +135,23 @@ pub fn register_user(
 /// message - message text
 /// name - user name
 /// password - user password
+/// dialect - server dialect (error if it has no auth)
 ///
-/// returns 0 if the message was sent successfully
-/// returns 1 if the user does not exist
-/// returns 2 if the password is incorrect
+/// returns auth result (message is sent only if it is [`AuthResult::Sent`])
 pub fn send_message_auth(
     stream: &mut WebSocket<impl Write + Read>,
     name: &str,
     password: &str,
     message: &str,
-) -> Result<u8, Box<dyn Error>> {
-    stream.write(Message::Binary(
-        format!("\x02{name}\n{password}\n{message}")
-            .as_bytes()
-            .to_vec()
-            .into(),
-    ))?;
-    stream.flush()?;
-    if let Ok(msg) = stream.read() {
-        if msg.is_binary() {
-            Ok(0)
-        } else {
-            Ok(*msg.into_data().get(0).unwrap_or(&0))
-        }
-    } else {
-        Ok(0)
-    }
+    dialect: Dialect,
+) -> Result<AuthResult, Error> {
+    dialect.check_auth()?;
+    let reply = request_reply(
+        stream,
+        format!("\x02{name}\n{password}\n{message}"),
+        dialect,
+    )?;
+    Ok(AuthResult::from(reply.unwrap_or(0)))
 }
 
 /// Read messages
@@ -81,6 +159,7 @@ pub fn send_message_auth(
 /// max_messages - max messages in list
 /// last_size - last returned packet size
 /// chunked - is chunked reading enabled
+/// dialect - server dialect
 ///
 /// returns (messages, packet size)
 pub fn read_messages(
@@ -88,60 +167,95 @@ pub fn read_messages(
     max_messages: usize,
     last_size: usize,
     chunked: bool,
-) -> Result<Option<(Vec<String>, usize)>, Box<dyn Error>> {
+    dialect: Dialect,
+) -> Result<Option<(Vec<String>, usize)>, Error> {
+    Ok(read_messages_data(stream, last_size, chunked, dialect)?
+        .map(|(data, packet_size)| (dialect.parse_messages(&data, max_messages), packet_size)))
+}
+
+/// Read messages as the server sent them
+///
+/// last_size - last returned packet size
+/// chunked - is chunked reading enabled
+/// dialect - server dialect
+///
+/// returns (data, packet size), data is the whole history if it is not a chunk
+pub fn read_messages_data(
+    stream: &mut WebSocket<impl Write + Read>,
+    last_size: usize,
+    chunked: bool,
+    dialect: Dialect,
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
     stream.write(Message::Binary(vec![0x00].into()))?;
     stream.flush()?;
 
-    let packet_size = {
-        let msg = stream.read()?;
-        if !msg.is_binary() {
-            return Err("msg is not binary".into());
-        }
-        let len = msg.into_data().to_vec();
-
-        String::from_utf8(len)?
-            .trim_matches(char::from(0))
-            .parse()?
-    };
+    let packet_size = dialect.parse_size(&read_binary(stream)?)?;
 
     if last_size == packet_size {
         return Ok(None);
     }
 
-    let to_read = if !chunked || last_size == 0 {
+    let chunked = chunked && last_size != 0;
+
+    let to_read = if !chunked {
         stream.write(Message::Binary(vec![0x00, 0x01].into()))?;
         packet_size
     } else {
+        let offset = chunk_offset(last_size, packet_size)?;
         stream.write(Message::Binary(
-            format!("\x00\x02{}", last_size).as_bytes().to_vec().into(),
+            format!("\x00\x02{}", offset).as_bytes().to_vec().into(),
         ))?;
-        packet_size - last_size
+        packet_size - offset
     };
     stream.flush()?;
 
-    let msg = stream.read()?;
-    if !msg.is_binary() {
-        return Err("msg is not binary".into());
-    }
-    let packet_data = msg.into_data().to_vec();
+    let mut packet_data = read_binary(stream)?;
 
     if packet_data.len() > to_read {
-        return Err("too big msg".into());
+        return Err(Error::Protocol("too big msg".to_string()));
+    }
+
+    if chunked {
+        packet_data = chunk_data(packet_data, last_size, packet_size)?;
+    }
+
+    Ok(Some((packet_data, packet_size)))
+}
+
+/// Read raw history bytes starting from the offset
+///
+/// offset - offset in bytes (zero is the whole history)
+/// dialect - server dialect
+///
+/// returns (data, packet size)
+pub fn read_history(
+    stream: &mut WebSocket<impl Write + Read>,
+    offset: usize,
+    dialect: Dialect,
+) -> Result<(Vec<u8>, usize), Error> {
+    stream.write(Message::Binary(vec![0x00].into()))?;
+    stream.flush()?;
+
+    let packet_size = dialect.parse_size(&read_binary(stream)?)?;
+
+    if offset >= packet_size {
+        return Ok((Vec::new(), packet_size));
+    }
+
+    if offset == 0 {
+        stream.write(Message::Binary(vec![0x00, 0x01].into()))?;
+    } else {
+        stream.write(Message::Binary(
+            format!("\x00\x02{}", offset).as_bytes().to_vec().into(),
+        ))?;
+    }
+    stream.flush()?;
+
+    let data = read_binary(stream)?;
+
+    if data.len() > packet_size - offset {
+        return Err(Error::Protocol("too big msg".to_string()));
     }
 
-    let packet_data = String::from_utf8_lossy(&packet_data).to_string();
-
-    let lines: Vec<&str> = packet_data.split("\n").collect();
-    let lines: Vec<String> = lines
-        .clone()
-        .into_iter()
-        .skip(if lines.len() >= max_messages {
-            lines.len() - max_messages
-        } else {
-            0
-        })
-        .map(|o| o.to_string())
-        .collect();
-
-    Ok(Some((lines, packet_size)))
+    Ok((data, packet_size))
 }
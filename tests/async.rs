@@ -0,0 +1,269 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bRAC::proto::{
+    r#async::{
+        connect, read_history_page, read_messages, register_user, send_message, send_message_auth,
+        RacStream,
+    },
+    AuthResult, ConnectOptions, Dialect, RegisterResult, TlsVerification,
+};
+use common::{spawn_server, wait_for_size};
+
+const HISTORY: [&str; 2] = ["[01.01.2025 00:00] hello", "[01.01.2025 00:01] world"];
+
+async fn connect_to(url: &str) -> RacStream {
+    connect(
+        url,
+        None,
+        &TlsVerification::Insecure,
+        &ConnectOptions::default(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Run server that accepts one connection and never replies
+///
+/// returns its URL
+fn spawn_silent_server(wrac: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let _websocket = wrac.then(|| tungstenite::accept(stream.try_clone().unwrap()).unwrap());
+        thread::sleep(Duration::from_secs(5));
+    });
+
+    let scheme = if wrac { "wrac" } else { "rac" };
+    format!("{scheme}://{addr}")
+}
+
+/// Read all, send a message and read it in chunked mode
+///
+/// wrac - use one connection for everything (RAC server closes it after each request)
+async fn send_and_read(wrac: bool) {
+    let (server, url) = spawn_server(wrac, &HISTORY);
+    let mut stream = connect_to(&url).await;
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages, [HISTORY[0], HISTORY[1], ""]);
+    assert_eq!(size, server.storage.size());
+
+    if !wrac {
+        stream = connect_to(&url).await;
+    }
+    send_message(&mut stream, "<dude> hi").await.unwrap();
+    wait_for_size(&server, size);
+
+    if !wrac {
+        stream = connect_to(&url).await;
+    }
+    let (messages, new_size) = read_messages(&mut stream, 100, size, true, Dialect::Rac2)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 2, "{messages:?}");
+    assert!(
+        messages[0].ends_with("} (UNREGISTERED) <dude> hi"),
+        "{messages:?}"
+    );
+    assert_eq!(new_size, server.storage.size());
+
+    // nothing new
+    if !wrac {
+        stream = connect_to(&url).await;
+    }
+    assert_eq!(
+        read_messages(&mut stream, 100, new_size, true, Dialect::Rac2)
+            .await
+            .unwrap(),
+        None
+    );
+
+    // max messages keeps the last ones
+    if !wrac {
+        stream = connect_to(&url).await;
+    }
+    let (messages, _) = read_messages(&mut stream, 2, 0, false, Dialect::Rac2)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1], "");
+}
+
+#[tokio::test]
+async fn async_rac_send_and_read() {
+    send_and_read(false).await;
+}
+
+#[tokio::test]
+async fn async_wrac_send_and_read() {
+    send_and_read(true).await;
+}
+
+#[tokio::test]
+async fn async_rac_auth() {
+    // RAC server closes the connection after each request, so success is not waited for
+    let (server, url) = spawn_server(false, &HISTORY);
+
+    assert_eq!(
+        register_user(&mut connect_to(&url).await, "dude", "pass", Dialect::Rac2)
+            .await
+            .unwrap(),
+        RegisterResult::Registered
+    );
+    assert_eq!(
+        register_user(&mut connect_to(&url).await, "dude", "pass", Dialect::Rac2)
+            .await
+            .unwrap(),
+        RegisterResult::NameTaken
+    );
+    assert_eq!(
+        send_message_auth(
+            &mut connect_to(&url).await,
+            "dude",
+            "wrong",
+            "hi",
+            Dialect::Rac2
+        )
+        .await
+        .unwrap(),
+        AuthResult::WrongPassword
+    );
+
+    let size = server.storage.size();
+    assert_eq!(
+        send_message_auth(
+            &mut connect_to(&url).await,
+            "dude",
+            "pass",
+            "hi",
+            Dialect::Rac2
+        )
+        .await
+        .unwrap(),
+        AuthResult::Sent
+    );
+    wait_for_size(&server, size);
+
+    assert!(
+        register_user(&mut connect_to(&url).await, "dude", "pass", Dialect::Rac1_0)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn async_wrac_auth() {
+    // WRAC server sends nothing on success and keeps the connection open
+    let (server, url) = spawn_server(true, &HISTORY);
+    let mut stream = connect_to(&url).await;
+
+    let started = Instant::now();
+
+    assert_eq!(
+        register_user(&mut stream, "dude", "pass", Dialect::Rac2)
+            .await
+            .unwrap(),
+        RegisterResult::Registered
+    );
+    assert_eq!(
+        register_user(&mut stream, "dude", "pass", Dialect::Rac2)
+            .await
+            .unwrap(),
+        RegisterResult::NameTaken
+    );
+    assert_eq!(
+        send_message_auth(&mut stream, "dude", "wrong", "hi", Dialect::Rac2)
+            .await
+            .unwrap(),
+        AuthResult::WrongPassword
+    );
+    assert_eq!(
+        send_message_auth(&mut stream, "dude", "pass", "hi", Dialect::Rac2)
+            .await
+            .unwrap(),
+        AuthResult::Sent
+    );
+
+    // replies are not waited until the read timeout
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 4, "{messages:?}");
+    assert!(messages[2].ends_with("<dude> hi"), "{messages:?}");
+    assert_eq!(size, server.storage.size());
+}
+
+#[tokio::test]
+async fn async_wrac_history_page() {
+    let (server, url) = spawn_server(true, &HISTORY);
+    let mut stream = connect_to(&url).await;
+
+    assert_eq!(
+        send_message_auth(&mut stream, "nobody", "", "hi", Dialect::Rac2)
+            .await
+            .unwrap(),
+        AuthResult::UserNotFound
+    );
+
+    let size = server.storage.size();
+    send_message(&mut stream, "<dude> hi").await.unwrap();
+    wait_for_size(&server, size);
+
+    // page before the sent message
+    let (messages, start) = read_history_page(&mut stream, size, 1000, Dialect::Rac2)
+        .await
+        .unwrap();
+    assert_eq!(messages, HISTORY);
+    assert_eq!(start, 0);
+
+    // the page is cut at the message start
+    let (messages, start) = read_history_page(&mut stream, size, 30, Dialect::Rac2)
+        .await
+        .unwrap();
+    assert_eq!(messages, [HISTORY[1]]);
+    assert_eq!(start, HISTORY[0].len() + 1);
+}
+
+#[tokio::test]
+async fn async_auth_read_error() {
+    // read timeout is an error, not a reply that tells the message was sent
+    for wrac in [false, true] {
+        let options = ConnectOptions {
+            read_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let mut stream = connect(
+            &spawn_silent_server(wrac),
+            None,
+            &TlsVerification::Insecure,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert!(
+            send_message_auth(&mut stream, "dude", "pass", "hi", Dialect::Rac2)
+                .await
+                .is_err(),
+            "wrac: {wrac}"
+        );
+    }
+}
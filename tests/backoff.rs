@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use bRAC::chat::connection::Backoff;
+
+#[test]
+fn backoff_grows_with_jitter() {
+    let backoff = Backoff {
+        base: Duration::from_secs(1),
+        max: Duration::from_secs(60),
+    };
+
+    for (attempt, full) in [(1, 1), (2, 2), (3, 4), (6, 32), (7, 60), (100, 60)] {
+        let full = Duration::from_secs(full);
+        for _ in 0..100 {
+            let delay = backoff.delay(attempt);
+            assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+        }
+    }
+}
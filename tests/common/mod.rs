@@ -0,0 +1,97 @@
+//! Fixtures shared by the integration tests
+
+// every test file uses only some of them
+#![allow(dead_code)]
+
+use std::{
+    net::TcpListener,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{env, fs, path::PathBuf};
+
+use bRAC::proto::server::Server;
+
+/// How long the server may take to handle a request, so broken tests fail instead of hanging
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn in_memory_server(history: &[&str]) -> Arc<Server> {
+    let server = Arc::new(Server::in_memory());
+
+    for message in history {
+        server.storage.push(message);
+    }
+
+    server
+}
+
+/// Run in-memory server with the history on a random local port
+///
+/// returns server and its URL (`rac://` or `wrac://`)
+pub fn spawn_server(wrac: bool, history: &[&str]) -> (Arc<Server>, String) {
+    let server = in_memory_server(history);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn({
+        let server = server.clone();
+        move || {
+            let _ = server.run(listener, wrac);
+        }
+    });
+
+    let scheme = if wrac { "wrac" } else { "rac" };
+    (server, format!("{scheme}://{addr}"))
+}
+
+/// Run in-memory server on a new socket in the temp dir
+///
+/// name - socket name, unique for the test
+///
+/// returns server and its URL (`rac+unix://` or `wrac+unix://`)
+#[cfg(unix)]
+pub fn spawn_unix_server(name: &str, wrac: bool) -> (Arc<Server>, String) {
+    let server = in_memory_server(&[]);
+
+    let path: PathBuf = env::temp_dir().join(format!("bRAC-{}-{name}.sock", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let scheme = if wrac { "wrac" } else { "rac" };
+    let url = format!("{scheme}+unix://{}", path.display());
+
+    thread::spawn({
+        let url = url.clone();
+        let server = server.clone();
+        move || {
+            let _ = server.listen(&url);
+        }
+    });
+
+    wait_until(|| path.exists(), "socket was not created");
+
+    (server, url)
+}
+
+/// Wait until the server history is not `size` bytes anymore
+/// (sent messages are handled in another thread)
+pub fn wait_for_size(server: &Server, size: usize) {
+    wait_until(|| server.storage.size() != size, "message was not stored");
+}
+
+/// Wait until the condition is true
+///
+/// panics with the message if it takes longer than [`SERVER_TIMEOUT`]
+pub fn wait_until(mut condition: impl FnMut() -> bool, message: &str) {
+    let started = Instant::now();
+
+    while !condition() {
+        if started.elapsed() > SERVER_TIMEOUT {
+            panic!("{message}");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
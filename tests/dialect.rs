@@ -0,0 +1,116 @@
+use bRAC::proto::{dialect::MessageLine, AuthResult, Dialect, RegisterResult};
+
+#[test]
+fn parse_size() {
+    for dialect in Dialect::ALL {
+        assert_eq!(dialect.parse_size(b"123").unwrap(), 123);
+        assert_eq!(dialect.parse_size(b"\x00\x00123\0\0").unwrap(), 123);
+        assert_eq!(dialect.parse_size(b"123\r\n").unwrap(), 123);
+        assert!(dialect.parse_size(b"abc").is_err());
+    }
+}
+
+#[test]
+fn parse_messages() {
+    let data = b"[01.01.2025 00:00] a\r\n[01.01.2025 00:01] b\r\n";
+
+    assert_eq!(
+        Dialect::Rac1_0.parse_messages(data, 100),
+        vec!["[01.01.2025 00:00] a", "[01.01.2025 00:01] b", ""]
+    );
+    assert_eq!(
+        Dialect::Rac2.parse_messages(data, 2),
+        vec!["[01.01.2025 00:01] b\r", ""]
+    );
+}
+
+#[test]
+fn messages_start() {
+    let data = b"[01.01.2025 00:00] a\xff\xfe\r\n[01.01.2025 00:01] b\r\n";
+
+    assert_eq!(Dialect::Rac1_0.messages_start(data, 100), 0);
+    assert_eq!(Dialect::Rac1_0.messages_start(data, 3), 0);
+    // offsets are in the raw bytes, not in the parsed lines
+    assert_eq!(Dialect::Rac1_0.messages_start(data, 2), 24);
+    assert_eq!(Dialect::Rac1_0.messages_start(data, 1), data.len());
+}
+
+#[test]
+fn parse_line() {
+    assert_eq!(
+        Dialect::Rac2.parse_line("[01.01.2025 00:00] {127.0.0.1} (UNREGISTERED) <dude> hi"),
+        Some(MessageLine {
+            date: "01.01.2025 00:00".to_string(),
+            ip: Some("127.0.0.1".to_string()),
+            unauthenticated: true,
+            text: "<dude> hi".to_string(),
+        })
+    );
+    assert_eq!(
+        Dialect::Crab.parse_line("[01.01.2025 00:00] (UNAUTHENTICATED) {::1} <dude> hi"),
+        Some(MessageLine {
+            date: "01.01.2025 00:00".to_string(),
+            ip: Some("::1".to_string()),
+            unauthenticated: true,
+            text: "<dude> hi".to_string(),
+        })
+    );
+
+    // RACv1.0 has no ips, so braces are the part of the text
+    let line = Dialect::Rac1_0
+        .parse_line("[01.01.2025 00:00] {not ip} hi")
+        .unwrap();
+    assert_eq!(line.ip, None);
+    assert_eq!(line.text, "{not ip} hi");
+
+    assert_eq!(Dialect::Rac2.parse_line("no date"), None);
+}
+
+#[test]
+fn auth() {
+    assert!(Dialect::Rac1_0.check_auth().is_err());
+    assert!(Dialect::Rac1_99.check_auth().is_err());
+    assert!(Dialect::Rac2.check_auth().is_ok());
+
+    assert!(Dialect::Crab.check_auth().is_ok());
+
+    // reply codes don't depend on the dialect
+    assert_eq!(AuthResult::from(0), AuthResult::Sent);
+    assert_eq!(AuthResult::from(2), AuthResult::WrongPassword);
+    assert_eq!(RegisterResult::from(1), RegisterResult::NameTaken);
+}
+
+#[test]
+fn detect() {
+    let lines = |lines: &[&str]| lines.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(Dialect::detect(&[]), None);
+    assert_eq!(Dialect::detect(&lines(&["", "no date"])), None);
+    assert_eq!(
+        Dialect::detect(&lines(&["[01.01.2025 00:00] {127.0.0.1} hi"])),
+        Some(Dialect::Rac2)
+    );
+    assert_eq!(
+        Dialect::detect(&lines(&[
+            "[01.01.2025 00:00] {127.0.0.1} hi",
+            "[01.01.2025 00:00] (UNAUTHENTICATED) {127.0.0.1} hi",
+        ])),
+        Some(Dialect::Crab)
+    );
+
+    // servers may hide ips, and there may be no messages without auth yet
+    assert_eq!(
+        Dialect::detect(&lines(&["[01.01.2025 00:00] <dude> hi", ""])),
+        Some(Dialect::Rac2)
+    );
+
+    // only RACv1 servers end lines with \r\n and write seconds
+    assert_eq!(
+        Dialect::detect(&lines(&["[01.01.2025 00:00] hi\r", ""])),
+        Some(Dialect::Rac1_0)
+    );
+    assert_eq!(
+        Dialect::detect(&lines(&["[01.01.2025 00:00:15] {127.0.0.1} hi", ""])),
+        Some(Dialect::Rac1_99)
+    );
+}
@@ -0,0 +1,306 @@
+use std::collections::HashSet;
+
+use bRAC::{
+    chat::{
+        auth_message_format, message::parse_timezone, parse_message, AuthStatus, ChatMessage,
+        UserAgents,
+    },
+    proto::Dialect,
+};
+use chrono::{FixedOffset, NaiveDate, TimeDelta, TimeZone};
+
+/// Parse message of the UTC server with auth and the built-in clients
+fn parse(line: &str, dialect: Dialect) -> Option<ChatMessage> {
+    parse_message(
+        line.to_string(),
+        dialect,
+        FixedOffset::east_opt(0),
+        &UserAgents::default(),
+        true,
+    )
+}
+
+#[test]
+fn parse_chat_message() {
+    let raw = "[01.01.2025 12:30] {127.0.0.1} \u{B9AC}\u{3E70}<dude> hello\x06!!AR!!https://example.com/a.png";
+    let message = parse_message(
+        raw.to_string(),
+        Dialect::Rac2,
+        parse_timezone("+03:00"),
+        &UserAgents::default(),
+        true,
+    )
+    .unwrap();
+
+    assert_eq!(
+        message,
+        ChatMessage {
+            raw: raw.to_string(),
+            date: "01.01.2025 12:30".to_string(),
+            time: FixedOffset::east_opt(3 * 3600)
+                .unwrap()
+                .with_ymd_and_hms(2025, 1, 1, 12, 30, 0)
+                .single(),
+            ip: Some("127.0.0.1".to_string()),
+            author: Some("dude".to_string()),
+            client: Some("bRAC".to_string()),
+            color: Some("#70fa7a".to_string()),
+            auth: AuthStatus::Unknown,
+            avatar: Some("https://example.com/a.png".to_string()),
+            text: "hello".to_string(),
+        }
+    );
+}
+
+#[test]
+fn parse_chat_message_auth() {
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} (UNREGISTERED) <dude> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.auth, AuthStatus::Unauthenticated);
+    assert_eq!(message.client.as_deref(), Some("clRAC"));
+
+    let message = parse("[01.01.2025 12:30] <dude> hi", Dialect::Rac1_0).unwrap();
+    assert_eq!(message.auth, AuthStatus::Unknown);
+    assert_eq!(message.ip, None);
+}
+
+#[test]
+fn parse_system_message() {
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} server restarted",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.author, None);
+    assert_eq!(message.client, None);
+    assert_eq!(message.text, "server restarted");
+
+    // strange dates are kept as they are
+    let message = parse("[yesterday] <dude> hi", Dialect::Rac2).unwrap();
+    assert_eq!(message.date, "yesterday");
+    assert_eq!(message.time, None);
+
+    assert_eq!(parse("", Dialect::Rac2), None);
+    assert_eq!(parse("no date", Dialect::Rac2), None);
+
+    let message = ChatMessage::system("no date");
+    assert_eq!(message.author, None);
+    assert!(message.time.is_some());
+}
+
+#[test]
+fn chat_message_serde() {
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} (UNREGISTERED) \u{2042}<dude> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+
+    let text = serde_yml::to_string(&message).unwrap();
+    assert!(text.contains("auth: unauthenticated"), "{text}");
+    assert!(text.contains("client: cRACk"), "{text}");
+
+    assert_eq!(serde_yml::from_str::<ChatMessage>(&text).unwrap(), message);
+}
+
+#[test]
+fn parse_timezones() {
+    assert_eq!(parse_timezone("UTC"), FixedOffset::east_opt(0));
+    assert_eq!(parse_timezone("Z"), FixedOffset::east_opt(0));
+    assert_eq!(parse_timezone("+03:00"), FixedOffset::east_opt(3 * 3600));
+    assert_eq!(parse_timezone("UTC+3"), FixedOffset::east_opt(3 * 3600));
+    assert_eq!(
+        parse_timezone("-0530"),
+        FixedOffset::west_opt(5 * 3600 + 30 * 60)
+    );
+    assert_eq!(parse_timezone("GMT-05:00"), FixedOffset::west_opt(5 * 3600));
+    assert_eq!(parse_timezone("+25"), None);
+    assert_eq!(parse_timezone("Europe/Moscow"), None);
+    assert_eq!(parse_timezone("+ж"), None);
+}
+
+#[test]
+fn parse_dates() {
+    let date = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+
+    assert_eq!(
+        Dialect::Rac2.parse_date("01.01.2025 12:30"),
+        date.and_hms_opt(12, 30, 0)
+    );
+    assert_eq!(Dialect::Rac2.parse_date("01.01.2025 12:30:15"), None);
+    assert_eq!(
+        Dialect::Rac1_99.parse_date("01.01.2025 12:30:15"),
+        date.and_hms_opt(12, 30, 15)
+    );
+    assert_eq!(Dialect::Rac2.parse_date("12:30"), None);
+}
+
+#[test]
+fn local_date() {
+    let message = parse_message(
+        "[01.01.2025 12:30] <dude> hi".to_string(),
+        Dialect::Rac2,
+        parse_timezone("+03:00"),
+        &UserAgents::default(),
+        true,
+    )
+    .unwrap();
+
+    let time = message.time.unwrap();
+    assert_eq!(
+        time.naive_utc(),
+        NaiveDate::from_ymd_opt(2025, 1, 1)
+            .unwrap()
+            .and_hms_opt(9, 30, 0)
+            .unwrap()
+    );
+
+    let local = time.with_timezone(&chrono::Local);
+    assert_eq!(
+        message.local_date("%H:%M"),
+        local.format("%H:%M").to_string()
+    );
+
+    // invalid format and unknown date are shown as the server sent them
+    assert_eq!(message.local_date("%Q"), "01.01.2025 12:30");
+
+    let message = parse("[yesterday] <dude> hi", Dialect::Rac2).unwrap();
+    assert_eq!(message.local_date("%H:%M"), "yesterday");
+}
+
+#[test]
+fn group_messages() {
+    let first = parse("[01.01.2025 12:30] <dude> a", Dialect::Rac2).unwrap();
+
+    let mut second = first.clone();
+    second.time = first.time.map(|o| o + TimeDelta::minutes(19));
+    assert!(second.is_grouped_with(&first));
+
+    // a new group starts after the window
+    let mut second = first.clone();
+    second.time = first.time.map(|o| o + TimeDelta::minutes(45));
+    second.date = "01.01.2025 13:15".to_string();
+    let third = ChatMessage {
+        time: second.time.map(|o| o + TimeDelta::minutes(1)),
+        ..second.clone()
+    };
+    assert!(!second.is_grouped_with(&first));
+    assert!(third.is_grouped_with(&second));
+
+    let mut other = first.clone();
+    other.author = Some("other".to_string());
+    assert!(!other.is_grouped_with(&first));
+
+    // same name from another client is shown separately
+    let mut other = first.clone();
+    other.client = Some("bRAC".to_string());
+    assert!(!other.is_grouped_with(&first));
+
+    let mut other = first.clone();
+    other.auth = AuthStatus::Unauthenticated;
+    assert!(!other.is_grouped_with(&first));
+
+    // unknown dates are compared as they are
+    let strange = parse("[now] <dude> a", Dialect::Rac2).unwrap();
+    assert!(strange.is_grouped_with(&strange.clone()));
+    assert!(!strange.is_grouped_with(&first));
+}
+
+#[test]
+fn auth_mode_format() {
+    assert_eq!(
+        auth_message_format("\u{B9AC}\u{3E70}<{name}> {text}"),
+        "\u{B9AC}\u{3E70}<{name}> {text}"
+    );
+    assert_eq!(
+        auth_message_format("\u{2042}{name}: {text}"),
+        "\u{2042}<{name}> {text}"
+    );
+    assert_eq!(auth_message_format("<{name}> {text}"), "<{name}> {text}");
+    assert_eq!(auth_message_format("{text} (by {name})"), "<{name}> {text}");
+    assert_eq!(auth_message_format("{text}"), "<{name}> {text}");
+}
+
+#[test]
+fn auth_mode_parse() {
+    // server adds the checked name before the message
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} <dude> \u{B9AC}\u{3E70}<admin> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.author.as_deref(), Some("dude"));
+    assert_eq!(message.client.as_deref(), Some("bRAC"));
+    assert_eq!(message.text, "hi");
+    assert_eq!(message.verified_author(), Some("dude"));
+
+    // only the name at the start is added by the server
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} \u{2042}<dude> <admin> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.author.as_deref(), Some("dude"));
+    assert_eq!(message.client.as_deref(), Some("cRACk"));
+    assert_eq!(message.text, "<admin> hi");
+    assert_eq!(message.verified_author(), None);
+
+    // names written by the sender are not verified
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} \u{B9AC}\u{3E70}<admin> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.author.as_deref(), Some("admin"));
+    assert_eq!(message.client.as_deref(), Some("bRAC"));
+    assert_eq!(message.auth, AuthStatus::Unknown);
+    assert_eq!(message.verified_author(), None);
+
+    // server name without the user agent
+    let message = parse("[01.01.2025 12:30] {127.0.0.1} <dude> hi", Dialect::Rac2).unwrap();
+    assert_eq!(message.verified_author(), Some("dude"));
+    assert_eq!(message.text, "hi");
+
+    let verified = HashSet::from(["dude".to_string()]);
+    assert!(!message.is_impersonation(&verified));
+
+    let message = parse(
+        "[01.01.2025 12:30] {127.0.0.1} (UNREGISTERED) \u{B9AC}\u{3E70}<dude> hi",
+        Dialect::Rac2,
+    )
+    .unwrap();
+    assert_eq!(message.author.as_deref(), Some("dude"));
+    assert_eq!(message.verified_author(), None);
+    assert!(message.is_impersonation(&verified));
+    assert!(!message.is_impersonation(&HashSet::new()));
+
+    // no auth in the dialect, nothing is verified or flagged
+    let message = parse("[01.01.2025 12:30] <dude> hi", Dialect::Rac1_0).unwrap();
+    assert_eq!(message.verified_author(), None);
+    assert!(!message.is_impersonation(&verified));
+}
+
+#[test]
+fn auth_mode_unknown_server() {
+    // anyone can write the name and the mark on a server without auth
+    for line in [
+        "[01.01.2025 12:30] {127.0.0.1} <dude> hi",
+        "[01.01.2025 12:30] {127.0.0.1} (UNREGISTERED) <dude> hi",
+    ] {
+        let message = parse_message(
+            line.to_string(),
+            Dialect::Rac2,
+            FixedOffset::east_opt(0),
+            &UserAgents::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(message.author.as_deref(), Some("dude"));
+        assert_eq!(message.auth, AuthStatus::Unknown);
+        assert_eq!(message.verified_author(), None);
+        assert!(!message.is_impersonation(&HashSet::from(["dude".to_string()])));
+    }
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use bRAC::proto::{
+    probe, server::Server, ConnectOptions, Dialect, ServerCapabilities, TlsVerification,
+    PROBE_WINDOW,
+};
+use common::spawn_server;
+
+fn probe_host(host: &str) -> ServerCapabilities {
+    probe(
+        host,
+        None,
+        &TlsVerification::Insecure,
+        &ConnectOptions::default(),
+        false,
+    )
+    .unwrap()
+}
+
+fn message_count(server: &Server) -> usize {
+    server
+        .storage
+        .read(0)
+        .iter()
+        .filter(|o| **o == b'\n')
+        .count()
+}
+
+#[test]
+fn probe_rac() {
+    let (server, url) = spawn_server(
+        false,
+        &[
+            "[01.01.2025 00:00] {127.0.0.1} <dude> hello",
+            "[01.01.2025 00:01] {127.0.0.1} <dude> world",
+        ],
+    );
+
+    let caps = probe_host(&url);
+
+    assert!(!caps.wrac);
+    assert_eq!(caps.chunked, Some(true));
+    // there are no unauthenticated messages, so auth is unknown
+    assert_eq!(caps.auth, None);
+    assert_eq!(caps.dialect, Some(Dialect::Rac2));
+
+    // nothing is written by the probe
+    assert_eq!(message_count(&server), 2);
+}
+
+#[test]
+fn probe_wrac() {
+    let (_, url) = spawn_server(true, &["[01.01.2025 00:00] <dude> hello"]);
+
+    let caps = probe_host(&url);
+
+    assert!(caps.wrac);
+    assert_eq!(caps.auth, None);
+    assert_eq!(caps.dialect, Some(Dialect::Rac2));
+}
+
+#[test]
+fn probe_empty_history() {
+    let (_, url) = spawn_server(true, &[]);
+
+    let caps = probe_host(&url);
+
+    assert_eq!(caps.chunked, None);
+    assert_eq!(caps.auth, None);
+    assert_eq!(caps.dialect, None);
+}
+
+#[test]
+fn probe_auth_by_marks() {
+    let (_, url) = spawn_server(
+        true,
+        &[
+            "[01.01.2025 00:00] {127.0.0.1} <dude> hello",
+            "[01.01.2025 00:01] {127.0.0.1} (UNAUTHENTICATED) <dude> world",
+        ],
+    );
+
+    let caps = probe_host(&url);
+
+    assert_eq!(caps.auth, Some(true));
+    assert_eq!(caps.dialect, Some(Dialect::Crab));
+}
+
+#[test]
+fn probe_auth_message() {
+    let (server, url) = spawn_server(false, &["[01.01.2025 00:00] {127.0.0.1} <dude> hello"]);
+
+    let caps = probe(
+        &url,
+        None,
+        &TlsVerification::Insecure,
+        &ConnectOptions::default(),
+        true,
+    )
+    .unwrap();
+
+    assert_eq!(caps.auth, Some(true));
+    assert_eq!(caps.dialect, Some(Dialect::Rac2));
+
+    // server with auth refuses the message
+    assert_eq!(message_count(&server), 1);
+}
+
+#[test]
+fn probe_big_history() {
+    let history: Vec<String> = (0..1000)
+        .map(|o| format!("[01.01.2025 00:00] {{127.0.0.1}} <dude> message {o}"))
+        .collect();
+    let history: Vec<&str> = history.iter().map(|o| o.as_str()).collect();
+
+    let (server, url) = spawn_server(true, &history);
+    assert!(server.storage.size() > PROBE_WINDOW * 2);
+
+    let caps = probe_host(&url);
+
+    assert_eq!(caps.chunked, Some(true));
+    assert_eq!(caps.dialect, Some(Dialect::Rac2));
+}
+
+#[test]
+fn probe_wrong_framing() {
+    let (_, url) = spawn_server(true, &[]);
+
+    let caps = probe_host(&url.replacen("wrac://", "rac://", 1));
+
+    assert!(caps.wrac);
+}
@@ -0,0 +1,534 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use bRAC::proto::{
+    connect,
+    mock::{MockStream, ScriptedServer},
+    rac::skip_null,
+    read_history_page, read_messages, register_user, send_message, send_message_auth, send_ping,
+    AuthResult, ConnectOptions, Dialect, Error, RacStream, RegisterResult, Stream, TlsVerification,
+};
+use common::spawn_server;
+
+const HISTORY: &str = "[01.01.2025 00:00] hello\n[01.01.2025 00:01] world\n";
+const FIRST: &str = "[01.01.2025 00:00] hello\n";
+
+#[test]
+fn mock_stream_chunks() {
+    let (mut client, mut server) = MockStream::pair();
+
+    std::io::Write::write_all(&mut client, b"abc").unwrap();
+    std::io::Write::write_all(&mut client, b"def").unwrap();
+    drop(client);
+
+    let mut buf = [0; 16];
+    assert_eq!(std::io::Read::read(&mut server, &mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"abc");
+    assert_eq!(std::io::Read::read(&mut server, &mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"def");
+    assert_eq!(std::io::Read::read(&mut server, &mut buf).unwrap(), 0);
+}
+
+#[test]
+fn skip_null_bytes() {
+    let mut stream = MockStream::with_input([&b"\0\0"[..], b"\0x", b"y"]);
+    assert_eq!(skip_null(&mut stream).unwrap(), b"x");
+    assert_eq!(skip_null(&mut stream).unwrap(), b"y");
+    assert!(skip_null(&mut stream).is_err());
+
+    let mut stream = MockStream::with_input([b"\0\0\0"]);
+    assert!(skip_null(&mut stream).is_err());
+}
+
+#[test]
+fn rac_read_messages() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x01", HISTORY)
+        .spawn(false)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        messages,
+        vec!["[01.01.2025 00:00] hello", "[01.01.2025 00:01] world", ""]
+    );
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_chunked() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(
+            format!("\x02{}", FIRST.len() - 1),
+            &HISTORY[FIRST.len() - 1..],
+        )
+        .spawn(false)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, FIRST.len(), true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(messages, vec!["[01.01.2025 00:01] world", ""]);
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_not_chunked() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x01", HISTORY)
+        .spawn(false)
+        .unwrap();
+
+    let (messages, _) = read_messages(&mut stream, 2, FIRST.len(), false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(messages, vec!["[01.01.2025 00:01] world", ""]);
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_unchanged() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .spawn(false)
+        .unwrap();
+
+    assert_eq!(
+        read_messages(&mut stream, 100, HISTORY.len(), true, Dialect::Rac2).unwrap(),
+        None
+    );
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_with_nulls() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", format!("\0\0{}\0\0", FIRST.len()))
+        .expect(b"\x01", format!("\0\0{FIRST}"))
+        .spawn(false)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(messages, vec!["[01.01.2025 00:00] hello", ""]);
+    assert_eq!(size, FIRST.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_send_message() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x01hello", b"")
+        .spawn(false)
+        .unwrap();
+
+    send_message(&mut stream, "hello").unwrap();
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_auth_replies() {
+    for (reply, result) in [
+        (&b""[..], AuthResult::Sent),
+        (b"\x01", AuthResult::UserNotFound),
+        (b"\x02", AuthResult::WrongPassword),
+        (b"\0\0\x02", AuthResult::WrongPassword),
+        (b"\x07", AuthResult::Unknown(7)),
+    ] {
+        let (mut stream, server) = ScriptedServer::new()
+            .expect(b"\x02user\npass\nhello", reply)
+            .spawn(false)
+            .unwrap();
+
+        assert_eq!(
+            send_message_auth(&mut stream, "user", "pass", "hello", Dialect::Rac2).unwrap(),
+            result
+        );
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
+
+#[test]
+fn rac_register_replies() {
+    for (reply, result) in [
+        (&b""[..], RegisterResult::Registered),
+        (b"\x01", RegisterResult::NameTaken),
+    ] {
+        let (mut stream, server) = ScriptedServer::new()
+            .expect(b"\x03user\npass", reply)
+            .spawn(false)
+            .unwrap();
+
+        assert_eq!(
+            register_user(&mut stream, "user", "pass", Dialect::Rac2).unwrap(),
+            result
+        );
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
+
+#[test]
+fn rac_auth_read_error() {
+    // server neither replies nor closes the connection, that is not a success
+    let (client, _server) = MockStream::pair();
+    client.set_read_timeout(Duration::from_millis(100));
+    let mut stream = RacStream::RAC(Box::new(client));
+
+    assert!(matches!(
+        send_message_auth(&mut stream, "user", "pass", "hello", Dialect::Rac2),
+        Err(Error::Io(_))
+    ));
+    assert!(matches!(
+        register_user(&mut stream, "user", "pass", Dialect::Rac2),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn wrac_read_messages() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .spawn(true)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        messages,
+        vec!["[01.01.2025 00:00] hello", "[01.01.2025 00:01] world", ""]
+    );
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_read_messages_chunked() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(
+            format!("\x00\x02{}", FIRST.len() - 1),
+            &HISTORY[FIRST.len() - 1..],
+        )
+        .spawn(true)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, FIRST.len(), true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(messages, vec!["[01.01.2025 00:01] world", ""]);
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_history_truncated() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", FIRST.len().to_string())
+        .spawn(false)
+        .unwrap();
+
+    assert!(matches!(
+        read_messages(&mut stream, 100, HISTORY.len(), true, Dialect::Rac2),
+        Err(Error::HistoryReset {
+            last_size,
+            packet_size
+        }) if last_size == HISTORY.len() && packet_size == FIRST.len()
+    ));
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_read_messages_history_changed() {
+    // history was trimmed and grew back, chunk starts in the middle of a message
+    let history = "[01.01.2025 00:05] something\n[01.01.2025 00:06] else\n";
+
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", history.len().to_string())
+        .expect(
+            format!("\x02{}", FIRST.len() - 1),
+            &history[FIRST.len() - 1..],
+        )
+        .spawn(false)
+        .unwrap();
+
+    assert!(matches!(
+        read_messages(&mut stream, 100, FIRST.len(), true, Dialect::Rac2),
+        Err(Error::HistoryReset { .. })
+    ));
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_read_messages_history_reset() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", FIRST.len().to_string())
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .spawn(true)
+        .unwrap();
+
+    assert!(matches!(
+        read_messages(&mut stream, 100, HISTORY.len(), true, Dialect::Rac2),
+        Err(Error::HistoryReset { .. })
+    ));
+
+    // full re-read on the same connection
+    let (messages, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_read_messages_not_chunked() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .expect(b"\x00", HISTORY.len().to_string())
+        .spawn(true)
+        .unwrap();
+
+    let (messages, _) = read_messages(&mut stream, 2, FIRST.len(), false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages, vec!["[01.01.2025 00:01] world", ""]);
+
+    // the same connection is used for the next request
+    assert_eq!(
+        read_messages(&mut stream, 2, HISTORY.len(), false, Dialect::Rac2).unwrap(),
+        None
+    );
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_read_messages_too_big() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(format!("\x00\x02{}", FIRST.len() - 1), HISTORY)
+        .spawn(true)
+        .unwrap();
+
+    assert!(read_messages(&mut stream, 100, FIRST.len(), true, Dialect::Rac2).is_err());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_read_messages_with_pings() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .with_pings()
+        .spawn(true)
+        .unwrap();
+
+    let (messages, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(messages.len(), 3);
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_keepalive_ping() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .spawn(true)
+        .unwrap();
+
+    // pong from the server is skipped by the read
+    send_ping(&mut stream).unwrap();
+
+    let (_, size) = read_messages(&mut stream, 100, 0, true, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(size, HISTORY.len());
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_server_close() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", b"")
+        .close()
+        .spawn(true)
+        .unwrap();
+
+    // close frame is not "msg is not binary"
+    assert!(matches!(
+        read_messages(&mut stream, 100, 0, true, Dialect::Rac2),
+        Err(Error::WebSocket(_))
+    ));
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_auth_replies() {
+    for (reply, result) in [
+        (&b""[..], AuthResult::Sent),
+        (b"\x01", AuthResult::UserNotFound),
+        (b"\x02", AuthResult::WrongPassword),
+        (b"\x07", AuthResult::Unknown(7)),
+    ] {
+        // size request tells that there is no reply
+        let (mut stream, server) = ScriptedServer::new()
+            .expect(b"\x02user\npass\nhello", reply)
+            .expect(b"\x00", b"12")
+            .spawn(true)
+            .unwrap();
+
+        assert_eq!(
+            send_message_auth(&mut stream, "user", "pass", "hello", Dialect::Rac2).unwrap(),
+            result
+        );
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
+
+#[test]
+fn wrac_register_replies() {
+    for (reply, result) in [
+        (&b""[..], RegisterResult::Registered),
+        (b"\x01", RegisterResult::NameTaken),
+    ] {
+        let (mut stream, server) = ScriptedServer::new()
+            .expect(b"\x03user\npass", reply)
+            .expect(b"\x00", b"12")
+            .spawn(true)
+            .unwrap();
+
+        assert_eq!(
+            register_user(&mut stream, "user", "pass", Dialect::Rac2).unwrap(),
+            result
+        );
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
+
+#[test]
+fn wrac_auth_keeps_connection() {
+    // server sends nothing on success and doesn't close the connection
+    let (server, url) = spawn_server(true, &[FIRST.trim_end()]);
+    let options = ConnectOptions::default();
+    let mut stream = connect(&url, None, &TlsVerification::Insecure, &options).unwrap();
+
+    let started = Instant::now();
+
+    assert_eq!(
+        register_user(&mut stream, "user", "pass", Dialect::Rac2).unwrap(),
+        RegisterResult::Registered
+    );
+    assert_eq!(
+        register_user(&mut stream, "user", "pass", Dialect::Rac2).unwrap(),
+        RegisterResult::NameTaken
+    );
+    assert_eq!(
+        send_message_auth(&mut stream, "user", "wrong", "hello", Dialect::Rac2).unwrap(),
+        AuthResult::WrongPassword
+    );
+    assert_eq!(
+        send_message_auth(&mut stream, "user", "pass", "hello", Dialect::Rac2).unwrap(),
+        AuthResult::Sent
+    );
+
+    // replies are not waited until the read timeout
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // and the connection is still in sync
+    let (messages, size) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages.len(), 3, "{messages:?}");
+    assert!(messages[1].ends_with("<user> hello"), "{messages:?}");
+    assert_eq!(size, server.storage.size());
+}
+
+#[test]
+fn rac_history_page() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(
+            format!("\x02{}", FIRST.len() - 1),
+            &HISTORY[FIRST.len() - 1..],
+        )
+        .spawn(false)
+        .unwrap();
+
+    // page is exactly the last message
+    let page_size = HISTORY.len() - FIRST.len();
+
+    assert_eq!(
+        read_history_page(&mut stream, HISTORY.len(), page_size, Dialect::Rac2).unwrap(),
+        (vec!["[01.01.2025 00:01] world".to_string()], FIRST.len())
+    );
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn rac_history_page_cuts_message() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(
+            format!("\x02{}", FIRST.len() - 5),
+            &HISTORY[FIRST.len() - 5..],
+        )
+        .spawn(false)
+        .unwrap();
+
+    // the message is bigger than the page, nothing is read
+    assert_eq!(
+        read_history_page(&mut stream, FIRST.len(), 4, Dialect::Rac2).unwrap(),
+        (Vec::new(), FIRST.len())
+    );
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn wrac_history_page_start() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x00", HISTORY.len().to_string())
+        .expect(b"\x00\x01", HISTORY)
+        .spawn(true)
+        .unwrap();
+
+    // the page reaches the history start
+    assert_eq!(
+        read_history_page(&mut stream, FIRST.len(), 1000, Dialect::Rac2).unwrap(),
+        (vec!["[01.01.2025 00:00] hello".to_string()], 0)
+    );
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn script_mismatch() {
+    let (mut stream, server) = ScriptedServer::new()
+        .expect(b"\x01hello", b"")
+        .spawn(false)
+        .unwrap();
+
+    send_message(&mut stream, "bye").unwrap();
+    assert!(server.join().unwrap().is_err());
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::{env, fs, path::PathBuf};
+
+use bRAC::proto::{
+    connect, read_messages, record::parse_capture, record::Direction, record::EntryKind,
+    record::Replay, send_message, server::Server, ConnectOptions, Dialect, Recorder,
+    TlsVerification,
+};
+use common::{spawn_server, wait_for_size};
+
+const HISTORY: [&str; 1] = ["[01.01.2025 00:00] {127.0.0.1} <dude> hello"];
+
+fn capture_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("bRAC-{}-{name}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Send and read messages with recording
+///
+/// returns read messages
+fn record_session(server: &Server, url: &str, path: &PathBuf) -> Vec<String> {
+    let options = ConnectOptions {
+        recorder: Some(Recorder::open(path).unwrap()),
+        ..Default::default()
+    };
+
+    let size = server.storage.size();
+
+    let mut stream = connect(url, None, &TlsVerification::Insecure, &options).unwrap();
+    send_message(&mut stream, "world").unwrap();
+
+    // connections are handled in parallel, so the message may be not saved yet
+    wait_for_size(server, size);
+
+    let mut stream = connect(url, None, &TlsVerification::Insecure, &options).unwrap();
+    let (messages, _) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    messages
+}
+
+/// Replay the read connection of [`record_session`]
+fn replay_session(path: &PathBuf) -> Vec<String> {
+    let mut replay = Replay::load(path).unwrap();
+    assert_eq!(replay.len(), 2);
+
+    // first connection only sends
+    replay.connect(&ConnectOptions::default()).unwrap();
+
+    let mut stream = replay.connect(&ConnectOptions::default()).unwrap();
+    let (messages, _) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    assert!(replay.is_empty());
+    assert!(replay.connect(&ConnectOptions::default()).is_err());
+
+    messages
+}
+
+#[test]
+fn record_and_replay_rac() {
+    let (server, url) = spawn_server(false, &HISTORY);
+    let path = capture_path("rac");
+
+    let messages = record_session(&server, &url, &path);
+    assert_eq!(messages.len(), 3, "{messages:?}");
+
+    let entries = parse_capture(&fs::read_to_string(&path).unwrap()).unwrap();
+
+    assert_eq!(entries[0].kind, EntryKind::Connect);
+    assert_eq!(entries[0].data, url.as_bytes());
+    assert!(entries
+        .iter()
+        .any(|o| o.connection == 1 && o.direction == Direction::Sent && o.data == b"\x01world"));
+    assert!(entries
+        .iter()
+        .all(|o| matches!(o.kind, EntryKind::Connect | EntryKind::Raw)));
+
+    assert_eq!(replay_session(&path), messages);
+}
+
+#[test]
+fn record_and_replay_wrac() {
+    let (server, url) = spawn_server(true, &HISTORY);
+    let path = capture_path("wrac");
+
+    let messages = record_session(&server, &url, &path);
+    assert_eq!(messages.len(), 3, "{messages:?}");
+
+    let entries = parse_capture(&fs::read_to_string(&path).unwrap()).unwrap();
+
+    // sent frames are masked in the raw data, but decoded
+    assert!(entries.iter().any(|o| o.connection == 1
+        && o.kind == EntryKind::Binary
+        && o.direction == Direction::Sent
+        && o.data == b"\x01world"));
+    assert!(entries.iter().any(|o| o.connection == 2
+        && o.kind == EntryKind::Binary
+        && o.direction == Direction::Received
+        && o.data.ends_with(b"world\n")));
+
+    assert_eq!(replay_session(&path), messages);
+}
+
+#[test]
+fn invalid_capture() {
+    assert!(parse_capture("# comment\n\n1 #1 > connect cmFjOi8vbG9jYWxob3N0\n").is_ok());
+    assert!(parse_capture("1 #1 > unknown AA==").is_err());
+    assert!(parse_capture("1 1 > raw AA==").is_err());
+    assert!(parse_capture("1 #1 > raw not-base64").is_err());
+}
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    sync::{Arc, RwLock},
+};
+
+use bRAC::proto::{
+    tls::{build_connector, check_pin, fingerprint, KnownHosts, PinStore, TlsMode},
+    Error, TlsVerification,
+};
+use native_tls::Certificate;
+
+const CERT_A: &str = "-----BEGIN CERTIFICATE-----
+MIIBfzCCASWgAwIBAgIUTQkI4nABGNYPBO/IT2geoK0SioIwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJYS5leGFtcGxlMCAXDTI2MTAxODA2MzYyMloYDzIxMjYwOTI0
+MDYzNjIyWjAUMRIwEAYDVQQDDAlhLmV4YW1wbGUwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAATozy0Re0ew5iae2c/mMuB4B56W5cuO33yK+grI8MBCz2NYLVw77wuD
+pICTdPgS4YCgyEN+CfgjuYsJjNC9Jvy2o1MwUTAdBgNVHQ4EFgQUMgp1lYfRpBk9
+Rqxn5oWD6SEhQ+UwHwYDVR0jBBgwFoAUMgp1lYfRpBk9Rqxn5oWD6SEhQ+UwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiBdlPEVyUf3PAcOgKC4+9SP
+1CRCsN2zNIMbE7kMoIpAsAIhAKSOeawwiL2mp01u+cQj7Vi+KipvseHhStyJBMGe
+BCSy
+-----END CERTIFICATE-----";
+
+const CERT_B: &str = "-----BEGIN CERTIFICATE-----
+MIIBfjCCASWgAwIBAgIUKyGqX1WiS1BvFHOGcgGXmIhpmyMwCgYIKoZIzj0EAwIw
+FDESMBAGA1UEAwwJYi5leGFtcGxlMCAXDTI2MTAxODA2MzYyMloYDzIxMjYwOTI0
+MDYzNjIyWjAUMRIwEAYDVQQDDAliLmV4YW1wbGUwWTATBgcqhkjOPQIBBggqhkjO
+PQMBBwNCAASJk4AoNyJsn3vIhuqNX0gSU3bT5ubcoPo6NASNDla2EFOsvVglcFul
+PretWleU6hcn0Vh4pJ5qkxn++HLM3W6no1MwUTAdBgNVHQ4EFgQUYiNXss6CJR0X
+EH70bRfqgnGf8okwHwYDVR0jBBgwFoAUYiNXss6CJR0XEH70bRfqgnGf8okwDwYD
+VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiB9EUA2qlO5D8uOPPuxT8Sq
+LzUALqAcAbeMSQvO3LHOIQIgbYB/MOeZKjK/Cjmk3a2jir1dN3EsFykH4apyeNGX
+pJw=
+-----END CERTIFICATE-----";
+
+const HOST: &str = "example.com:42666";
+
+/// Pinned fingerprints that are not saved anywhere
+#[derive(Default)]
+struct MemoryPins {
+    hosts: RwLock<HashMap<String, String>>,
+}
+
+impl MemoryPins {
+    fn forget(&self, host: &str) {
+        self.hosts.write().unwrap().remove(host);
+    }
+}
+
+impl PinStore for MemoryPins {
+    fn pinned(&self, host: &str) -> Option<String> {
+        self.hosts.read().unwrap().get(host).cloned()
+    }
+
+    fn pin(&self, host: &str, fingerprint: &str) -> Result<(), Error> {
+        self.hosts
+            .write()
+            .unwrap()
+            .insert(host.to_string(), fingerprint.to_string());
+        Ok(())
+    }
+}
+
+fn cert(pem: &str) -> Certificate {
+    Certificate::from_pem(pem.as_bytes()).unwrap()
+}
+
+fn tofu() -> (Arc<MemoryPins>, TlsVerification) {
+    let pins = Arc::new(MemoryPins::default());
+    (pins.clone(), TlsVerification::new(TlsMode::Tofu, pins))
+}
+
+#[test]
+fn certificate_fingerprint() {
+    assert_eq!(
+        fingerprint(&cert(CERT_A)).unwrap(),
+        "D4:BB:AD:E8:F8:33:60:AB:35:0A:C4:A3:05:4E:4A:36:\
+         13:C9:B0:91:65:F6:77:0F:32:49:3E:E3:2E:28:64:D2"
+    );
+    assert_ne!(
+        fingerprint(&cert(CERT_A)).unwrap(),
+        fingerprint(&cert(CERT_B)).unwrap()
+    );
+}
+
+#[test]
+fn tofu_pins_first_certificate() {
+    let (pins, tls) = tofu();
+
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+    assert_eq!(pins.pinned(HOST), Some(fingerprint(&cert(CERT_A)).unwrap()));
+
+    // the same certificate passes, other hosts are pinned separately
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+    check_pin(&tls, "example.com:443", Some(cert(CERT_B))).unwrap();
+    assert_eq!(pins.pinned(HOST), Some(fingerprint(&cert(CERT_A)).unwrap()));
+}
+
+#[test]
+fn tofu_rejects_changed_certificate() {
+    let (pins, tls) = tofu();
+
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+
+    match check_pin(&tls, HOST, Some(cert(CERT_B))) {
+        Err(Error::PinMismatch {
+            host,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(host, HOST);
+            assert_eq!(expected, fingerprint(&cert(CERT_A)).unwrap());
+            assert_eq!(actual, fingerprint(&cert(CERT_B)).unwrap());
+        }
+        result => panic!("expected pin mismatch, got {result:?}"),
+    }
+
+    // the pin is not replaced by the rejected certificate
+    assert_eq!(pins.pinned(HOST), Some(fingerprint(&cert(CERT_A)).unwrap()));
+    assert!(check_pin(&tls, HOST, None).is_err());
+}
+
+#[test]
+fn tofu_repins_after_replace() {
+    let (pins, tls) = tofu();
+
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+
+    // user trusts the new certificate
+    pins.pin(HOST, &fingerprint(&cert(CERT_B)).unwrap())
+        .unwrap();
+    check_pin(&tls, HOST, Some(cert(CERT_B))).unwrap();
+    assert!(check_pin(&tls, HOST, Some(cert(CERT_A))).is_err());
+
+    // forgotten host is pinned again on the next connection
+    pins.forget(HOST);
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+    assert_eq!(pins.pinned(HOST), Some(fingerprint(&cert(CERT_A)).unwrap()));
+}
+
+#[test]
+fn other_modes_skip_pins() {
+    let pins = Arc::new(MemoryPins::default());
+
+    for mode in [TlsMode::Strict, TlsMode::Insecure] {
+        let tls = TlsVerification::new(mode, pins.clone());
+        check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+        check_pin(&tls, HOST, None).unwrap();
+        build_connector(&tls).unwrap();
+    }
+
+    assert_eq!(pins.pinned(HOST), None);
+    build_connector(&tofu().1).unwrap();
+}
+
+#[test]
+fn known_hosts_file() {
+    let path = env::temp_dir().join(format!("bRAC-{}-known_hosts", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let hosts = KnownHosts::load(path.clone());
+    assert_eq!(hosts.pinned(HOST), None);
+
+    let tls = TlsVerification::new(TlsMode::Tofu, Arc::new(hosts));
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+
+    // pins are kept after restart
+    let tls = TlsVerification::new(TlsMode::Tofu, Arc::new(KnownHosts::load(path.clone())));
+    check_pin(&tls, HOST, Some(cert(CERT_A))).unwrap();
+    assert!(matches!(
+        check_pin(&tls, HOST, Some(cert(CERT_B))),
+        Err(Error::PinMismatch { .. })
+    ));
+
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn known_hosts_save_error() {
+    // parent of the file is a file, so it can't be written
+    let parent = env::temp_dir().join(format!("bRAC-{}-not-dir", std::process::id()));
+    fs::write(&parent, "").unwrap();
+
+    let hosts = Arc::new(KnownHosts::load(parent.join("known_hosts")));
+    let tls = TlsVerification::new(TlsMode::Tofu, hosts.clone());
+
+    assert!(matches!(
+        check_pin(&tls, HOST, Some(cert(CERT_A))),
+        Err(Error::PinSave(_))
+    ));
+
+    // the pin is still checked in this session
+    assert_eq!(
+        hosts.pinned(HOST),
+        Some(fingerprint(&cert(CERT_A)).unwrap())
+    );
+    assert!(check_pin(&tls, HOST, Some(cert(CERT_B))).is_err());
+
+    let _ = fs::remove_file(&parent);
+}
@@ -0,0 +1,41 @@
+#![cfg(unix)]
+
+mod common;
+
+use bRAC::proto::{
+    connect, read_messages, send_message, server::Server, ConnectOptions, Dialect, TlsVerification,
+};
+use common::{spawn_unix_server, wait_for_size};
+
+fn send_and_read(server: &Server, url: &str) {
+    let options = ConnectOptions::default();
+
+    let mut stream = connect(url, None, &TlsVerification::Insecure, &options).unwrap();
+    send_message(&mut stream, "hello").unwrap();
+
+    wait_for_size(server, 0);
+
+    let mut stream = connect(url, None, &TlsVerification::Insecure, &options).unwrap();
+    let (messages, _) = read_messages(&mut stream, 100, 0, false, Dialect::Rac2)
+        .unwrap()
+        .unwrap();
+
+    // no ip over unix sockets, message without auth is marked
+    assert_eq!(messages.len(), 2);
+    assert!(
+        messages[0].ends_with("] (UNREGISTERED) hello"),
+        "{messages:?}"
+    );
+}
+
+#[test]
+fn rac_over_unix_socket() {
+    let (server, url) = spawn_unix_server("rac", false);
+    send_and_read(&server, &url);
+}
+
+#[test]
+fn wrac_over_unix_socket() {
+    let (server, url) = spawn_unix_server("wrac", true);
+    send_and_read(&server, &url);
+}
@@ -0,0 +1,171 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use bRAC::proto::{
+    parse_rac_url,
+    url::{RacHost, RacScheme, RacUrl},
+};
+
+#[test]
+fn default_ports() {
+    for (url, scheme, port) in [
+        ("rac://meex.lol", RacScheme::Rac, 42666),
+        ("racs://meex.lol", RacScheme::Racs, 42667),
+        ("wrac://meex.lol", RacScheme::Wrac, 52666),
+        ("wracs://meex.lol", RacScheme::Wracs, 52667),
+        ("meex.lol", RacScheme::Rac, 42666),
+    ] {
+        let url = RacUrl::parse(url).unwrap();
+        assert_eq!(url.scheme, scheme);
+        assert_eq!(url.host, RacHost::Domain("meex.lol".to_string()));
+        assert_eq!(url.port, port);
+        assert_eq!(url.path, "");
+    }
+}
+
+#[test]
+fn explicit_ports() {
+    for (url, scheme) in [
+        ("rac://127.0.0.1:12345", RacScheme::Rac),
+        ("racs://127.0.0.1:12345", RacScheme::Racs),
+        ("wrac://127.0.0.1:12345", RacScheme::Wrac),
+        ("wracs://127.0.0.1:12345", RacScheme::Wracs),
+        ("127.0.0.1:12345", RacScheme::Rac),
+    ] {
+        let url = RacUrl::parse(url).unwrap();
+        assert_eq!(url.scheme, scheme);
+        assert_eq!(url.host, RacHost::Ipv4(Ipv4Addr::LOCALHOST));
+        assert_eq!(url.port, 12345);
+    }
+}
+
+#[test]
+fn ipv6_hosts() {
+    let url = RacUrl::parse("rac://[::1]").unwrap();
+    assert_eq!(url.host, RacHost::Ipv6(Ipv6Addr::LOCALHOST));
+    assert_eq!(url.port, 42666);
+    assert_eq!(url.address(), "[::1]:42666");
+
+    let url = RacUrl::parse("wracs://[::1]:8080/chat").unwrap();
+    assert_eq!(url.host, RacHost::Ipv6(Ipv6Addr::LOCALHOST));
+    assert_eq!(url.port, 8080);
+    assert_eq!(url.path, "/chat");
+
+    let url = RacUrl::parse("::1").unwrap();
+    assert_eq!(url.host, RacHost::Ipv6(Ipv6Addr::LOCALHOST));
+    assert_eq!(url.port, 42666);
+    assert_eq!(url.host.name(), "::1");
+}
+
+#[test]
+fn paths() {
+    let url = RacUrl::parse("wrac://example.com/chat/room").unwrap();
+    assert_eq!(url.address(), "example.com:52666");
+    assert_eq!(url.path, "/chat/room");
+
+    let url = RacUrl::parse("rac://127.0.0.1/").unwrap();
+    assert_eq!(url.path, "/");
+}
+
+#[test]
+fn display_round_trip() {
+    for url in [
+        "rac://meex.lol",
+        "racs://meex.lol:1234",
+        "wrac://127.0.0.1/chat",
+        "wracs://[::1]:8080/chat",
+        "wracs://[2001:db8::1]",
+    ] {
+        let parsed = RacUrl::parse(url).unwrap();
+        assert_eq!(parsed.to_string(), url);
+        assert_eq!(RacUrl::parse(&parsed.to_string()).unwrap(), parsed);
+    }
+
+    assert_eq!(
+        RacUrl::parse("127.0.0.1").unwrap().to_string(),
+        "rac://127.0.0.1"
+    );
+    assert_eq!(
+        RacUrl::parse("WRACS://meex.lol:52667").unwrap().to_string(),
+        "wracs://meex.lol"
+    );
+}
+
+#[test]
+fn invalid_urls() {
+    for url in [
+        "http://meex.lol",
+        "rac://",
+        "rac://meex.lol:port",
+        "rac://[::1",
+        "rac://[::1]x",
+        "rac://meex.lol:99999",
+    ] {
+        assert!(RacUrl::parse(url).is_err(), "{url} should not parse");
+    }
+}
+
+#[test]
+fn unix_sockets() {
+    let url = RacUrl::parse("rac+unix:///run/rac.sock").unwrap();
+    assert_eq!(url.scheme, RacScheme::Rac);
+    assert_eq!(url.host, RacHost::Unix("/run/rac.sock".to_string()));
+    assert_eq!(url.address(), "/run/rac.sock");
+    assert!(url.is_unix());
+    assert_eq!(url.to_string(), "rac+unix:///run/rac.sock");
+
+    let url = RacUrl::parse("WRAC+UNIX://rac.sock").unwrap();
+    assert!(url.is_wrac());
+    assert_eq!(url.to_string(), "wrac+unix://rac.sock");
+    assert_eq!(url.websocket_url(), "ws://localhost/");
+    assert_eq!(
+        url.websocket_request(&[]).unwrap().headers()["Host"],
+        "localhost"
+    );
+
+    assert_eq!(
+        parse_rac_url("wrac+unix:///run/rac.sock"),
+        Some(("/run/rac.sock".to_string(), false, true))
+    );
+
+    for url in [
+        "racs+unix:///run/rac.sock",
+        "rac+unix://",
+        "http+unix:///run/rac.sock",
+    ] {
+        assert!(RacUrl::parse(url).is_err(), "{url} should not parse");
+    }
+}
+
+#[test]
+fn legacy_parse_rac_url() {
+    assert_eq!(
+        parse_rac_url("wracs://127.0.0.1/"),
+        Some(("127.0.0.1:52667".to_string(), true, true))
+    );
+    assert_eq!(
+        parse_rac_url("rac://[::1]"),
+        Some(("[::1]:42666".to_string(), false, false))
+    );
+    assert_eq!(parse_rac_url("ftp://127.0.0.1"), None);
+}
+
+#[test]
+fn websocket_request() {
+    let url = RacUrl::parse("wracs://meex.lol:443/chat?room=1#top").unwrap();
+    assert_eq!(url.websocket_url(), "wss://meex.lol:443/chat?room=1");
+
+    let headers = [("Origin".to_string(), "https://meex.lol".to_string())];
+    let request = url.websocket_request(&headers).unwrap();
+    assert_eq!(request.uri().path(), "/chat");
+    assert_eq!(request.uri().query(), Some("room=1"));
+    assert_eq!(request.headers()["Host"], "meex.lol");
+    assert_eq!(request.headers()["Origin"], "https://meex.lol");
+
+    let url = RacUrl::parse("wrac://[::1]").unwrap();
+    assert_eq!(url.websocket_url(), "ws://[::1]:52666/");
+    let request = url.websocket_request(&[]).unwrap();
+    assert_eq!(request.headers()["Host"], "[::1]:52666");
+
+    let headers = [("Bad Header".to_string(), "x".to_string())];
+    assert!(url.websocket_request(&headers).is_err());
+}
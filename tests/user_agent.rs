@@ -0,0 +1,64 @@
+use bRAC::chat::{UserAgent, UserAgents};
+
+#[test]
+fn built_in_user_agents() {
+    let agents = UserAgents::built_in();
+
+    let (nick, text, agent) = agents.parse("\u{2550}\u{2550}\u{2550}<dude> hi").unwrap();
+    assert_eq!((nick.as_str(), text.as_str()), ("dude", "hi"));
+    assert_eq!(agent.name, "CRAB");
+    assert_eq!(agent.color, "#fa7070");
+
+    // markers are escaped, clRAC matches any name
+    assert_eq!(agents.parse("<a.b> hi").unwrap().2.name, "clRAC");
+    assert_eq!(agents.parse("just text"), None);
+}
+
+#[test]
+fn custom_user_agents() {
+    let custom = vec![
+        UserAgent::new("Hammer", "\u{262D}", "#ff0000"),
+        UserAgent {
+            regex: Some(r"\[(.*?)\]: (.*)".to_string()),
+            ..UserAgent::new("Brackets", "", "#00ff00")
+        },
+        UserAgent::new("CRAB", "\u{2550}", "#000000"),
+        UserAgent {
+            regex: Some("(.*".to_string()),
+            ..UserAgent::new("Invalid", "", "#000000")
+        },
+        UserAgent {
+            regex: Some("<.*>".to_string()),
+            ..UserAgent::new("No groups", "", "#000000")
+        },
+    ];
+
+    let agents = UserAgents::with_custom(&custom);
+
+    assert_eq!(agents.parse("\u{262D}<dude> hi").unwrap().2.name, "Hammer");
+    assert_eq!(agents.parse("[dude]: hi").unwrap().0, "dude");
+
+    // built-in with the same name is replaced
+    assert_eq!(agents.get("CRAB").unwrap().color, "#000000");
+    assert_eq!(
+        agents.agents().iter().filter(|o| o.name == "CRAB").count(),
+        1
+    );
+
+    assert!(agents.get("Invalid").is_none());
+    assert!(agents.get("No groups").is_none());
+    assert!(UserAgents::new(custom).is_err());
+
+    // custom clients are checked before the built-in ones
+    assert_eq!(agents.agents().last().unwrap().name, "clRAC");
+    assert_eq!(agents.parse("<dude> hi").unwrap().2.name, "clRAC");
+}
+
+#[test]
+fn user_agents_config() {
+    let agent: UserAgent =
+        serde_yml::from_str("name: Hammer\nmarker: \"\u{262D}\"\ncolor: \"#ff0000\"\n").unwrap();
+
+    assert_eq!(agent, UserAgent::new("Hammer", "\u{262D}", "#ff0000"));
+    assert_eq!(agent.pattern(), "\u{262D}<(.*?)> (.*)");
+}